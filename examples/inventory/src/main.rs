@@ -6,14 +6,21 @@ extern crate sparkle;
 
 use std::ops::Deref;
 use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
 
 use sparkle::prelude::*;
-use sparkle::component;
+use sparkle::component::{self, EntityReferrer};
 
 #[component]
 struct InventoryItem {
     name: String,
-    owner: Entity
+    owner: EntityRef
+}
+
+impl EntityReferrer for InventoryItem {
+    fn each_ref(&mut self, func: &mut FnMut(&mut EntityRef)) {
+        func(&mut self.owner);
+    }
 }
 
 #[component]
@@ -32,20 +39,16 @@ impl InventoryView {
         }
     }
     
-    fn update(&mut self, mentity: &MetaEntity) {
+    fn update(&mut self, cm: &ComponentMapper, mentity: &MetaEntity) {
         if mentity.components.contains(&component::index_of::<InventoryOwner>()) {
-            self.map.insert(mentity.entity, HashSet::new());
+            self.inventory_of(mentity.entity);
         }
-    }
-    
-    fn update_item(&mut self, cm: &ComponentMapper, mentity: &MetaEntity) {
         if mentity.components.contains(&component::index_of::<InventoryItem>()) {
             for (_, items) in self.map.iter_mut() {
                 items.remove(&mentity.entity);
             }
             let item = cm.get::<InventoryItem>(mentity.entity);
-            let inventory = self.map.get_mut(&item.owner).expect("unvalid item owner");
-            inventory.insert(mentity.entity);
+            self.inventory_of(item.owner.unwrap()).insert(mentity.entity);
         }
     }
     
@@ -55,6 +58,13 @@ impl InventoryView {
             items.remove(&entity);
         }
     }
+
+    fn inventory_of(&mut self, owner: Entity) -> &mut HashSet<Entity> {
+        match self.map.entry(owner) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(HashSet::new())
+        }
+    }
 }
 
 impl Deref for InventoryView {
@@ -64,35 +74,21 @@ impl Deref for InventoryView {
     }
 }
 
-type InventoryCommandArg<'a> = (&'a mut InventoryView,
-                                &'a mut EntityMapper,
-                                &'a mut ComponentMapper);
-type InventoryCommand = Box<for<'a> Command<Args = InventoryCommandArg<'a>>>;
-
 struct InventoryMaintainer {
-    inventory_view: BlackboardEntry<InventoryView>,
-    query_recvr: CommandReceiver<InventoryCommand>
+    inventory_view: BlackboardEntry<InventoryView>
 }
 
 impl InventoryMaintainer {
-    fn new(blackboard: &Blackboard, query_recvr: CommandReceiver<InventoryCommand>) -> InventoryMaintainer {
+    fn new(blackboard: &Blackboard) -> InventoryMaintainer {
         InventoryMaintainer {
-            inventory_view: blackboard.get_entry("inventory_view"),
-            query_recvr: query_recvr
+            inventory_view: blackboard.get_entry("inventory_view")
         }
     }
 }
 
 impl System for InventoryMaintainer {
-    fn update(&mut self, em: &mut EntityMapper, cm: &mut ComponentMapper, _dt: f32) {
-        let mut inventory_view = self.inventory_view.borrow_mut();
-        while let Some(mut cmd) = self.query_recvr.recv() {
-            cmd.run((&mut *inventory_view, em, cm));
-        }
-    }
-
-    fn on_entity_changed(&mut self, _cm: &ComponentMapper, mentity: &MetaEntity) {
-        self.inventory_view.borrow_mut().update(mentity);
+    fn on_entity_changed(&mut self, cm: &ComponentMapper, mentity: &MetaEntity) {
+        self.inventory_view.borrow_mut().update(cm, mentity);
     }
 
     fn on_entity_removed(&mut self, _cm: &ComponentMapper, mentity: &MetaEntity) {
@@ -100,39 +96,11 @@ impl System for InventoryMaintainer {
     }
 }
 
-struct CreateItem {
-    item_e: Entity,
-    item: Option<InventoryItem>
-}
-
-impl<'a> Command for CreateItem {
-    type Args = InventoryCommandArg<'a>;
-    fn run(&mut self, args: InventoryCommandArg<'a>) {
-        let (view, em, cm) = args;
-        
-        let mitem = em.get_mentity_mut(self.item_e);
-        cm.insert(mitem, self.item.take().unwrap());
-        view.update_item(&*cm, &*mitem);
-    }
-}
-
-struct SetItemOwner {
-    item_e: Entity,
-    owner_e: Entity
-}
-
-impl<'a> Command for SetItemOwner {
-    type Args = InventoryCommandArg<'a>;
-    fn run(&mut self, args: InventoryCommandArg<'a>) {
-        let (view, em, cm) = args;
-        
-        {
-            let item = cm.get_mut::<InventoryItem>(self.item_e);
-            item.owner = self.owner_e;
-        }
-        let mitem = em.get_mentity(self.item_e);
-        view.update_item(&*cm, mitem);
-    }
+fn give_item(commands: &mut EntityCommands, item_e: Entity, name: &str, owner_e: Entity) {
+    commands.insert_referrer(item_e, InventoryItem {
+        name: name.to_string(),
+        owner: EntityRef::new(owner_e, RefPolicy::Cascade)
+    });
 }
 
 struct InventoryDisplayer {
@@ -148,7 +116,8 @@ impl InventoryDisplayer {
 }
 
 impl System for InventoryDisplayer {
-    fn update(&mut self, _em: &mut EntityMapper, cm: &mut ComponentMapper, _dt: f32) {
+    fn update(&mut self, _em: &mut EntityMapper, cm: &mut ComponentMapper,
+              _commands: &mut EntityCommands, _dt: f32) {
         println!("======= Inventories: =======");
         for (&owner, items) in self.inventory_view.borrow().iter() {
             let owner = cm.get::<InventoryOwner>(owner);
@@ -163,19 +132,10 @@ impl System for InventoryDisplayer {
 }
 
 macro_rules! expand_inventory {
-    (of $owner:ident with $($item:ident),* using $space:ident and $sender:ident) => ({
-        let owner = $owner;
+    (of $owner:ident with $($item:ident),* using $commands:ident) => ({
         $(
-            let $item = $space.em.create_entity();
-        )*
-        $(
-            {
-                let name = stringify!($item).to_string();
-                $sender.send(Box::new(CreateItem {
-                    item_e: $item,
-                    item: Some(InventoryItem { name: name, owner: owner })
-                }));
-            }
+            let $item = $commands.spawn();
+            give_item(&mut $commands, $item, stringify!($item), $owner);
         )*
         ($($item),*)
     })
@@ -186,28 +146,24 @@ fn main() {
     blackboard.insert("inventory_view", InventoryView::new());
     
     let (mut space, _) = Space::new();
-    let (mut inventory_cmd_sender, inventory_cmd_rcvr) = sparkle::command::stream();
-    space.sm.insert(InventoryMaintainer::new(&blackboard, inventory_cmd_rcvr));
+    space.sm.insert(InventoryMaintainer::new(&blackboard));
     space.sm.insert(InventoryDisplayer::new(&blackboard));
     
-    let bob = space.em.create_entity();
-    {
-        let mbob = space.em.get_mentity_mut(bob);
-        space.cm.insert(mbob, InventoryOwner { name: "bob".to_string() } );
-    }
-    expand_inventory!(of bob with hat, boots using space and inventory_cmd_sender);
+    let mut commands = EntityCommands::new(&space.em);
+    let bob = commands.spawn();
+    commands.insert(bob, InventoryOwner { name: "bob".to_string() });
+    expand_inventory!(of bob with hat, boots using commands);
     
-    let joe = space.em.create_entity();
-    {
-        let mjoe = space.em.get_mentity_mut(joe);
-        space.cm.insert(mjoe, InventoryOwner { name: "joe".to_string() } );
-    }
-    let (_, crowbar) = expand_inventory!(of joe with food, crowbar using space and inventory_cmd_sender);
+    let joe = commands.spawn();
+    commands.insert(joe, InventoryOwner { name: "joe".to_string() });
+    let (_, crowbar) = expand_inventory!(of joe with food, crowbar using commands);
+    commands.apply(&mut space.em, &mut space.cm);
     
     space.update(0.);
     
     println!("* bob steals joe's crowbar *");
-    inventory_cmd_sender.send(Box::new(SetItemOwner { item_e: crowbar, owner_e: bob }));
+    give_item(&mut commands, crowbar, "crowbar", bob);
+    commands.apply(&mut space.em, &mut space.cm);
     
     space.update(0.);
 }
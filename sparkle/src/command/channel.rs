@@ -0,0 +1,175 @@
+//! Named command channels.
+//!
+//! A `Space` and its `SystemMapper` receive commands from several named channels,
+//! processed in a configurable order, so that for instance the spawns are run
+//! before the gameplay commands and the despawns after them:
+//!
+//! ````ignore
+//! let mut spawn = space.add_command_channel("spawn");
+//! let mut despawn = space.add_command_channel("despawn");
+//! space.set_command_channel_order(&["spawn", DEFAULT_CHANNEL, "despawn"]);
+//! ````
+//!
+//! The commands of a frame are taken from the channels before any of them is run,
+//! so changing the order from a command only applies from the next frame.
+
+use command::{self, CommandSender, CommandReceiver};
+
+/// The name of the channel that always exists.
+pub const DEFAULT_CHANNEL: &'static str = "default";
+
+/// A named command stream.
+struct Channel<C> {
+    name: String,
+    sender: CommandSender<C>,
+    receiver: CommandReceiver<C>
+}
+
+/// Named command channels, processed in a configurable order.
+pub struct Channels<C> {
+    channels: Vec<Channel<C>>
+}
+
+impl<C> Channels<C> {
+    /// Creates the channels, with only the default one.
+    pub fn new() -> Channels<C> {
+        let mut channels = Channels {
+            channels: Vec::new()
+        };
+        channels.add(DEFAULT_CHANNEL);
+        channels
+    }
+
+    /// Adds a channel, processed after the existing ones, and returns its sender.
+    ///
+    /// If the channel already exists, its sender is returned.
+    pub fn add(&mut self, name: &str) -> CommandSender<C> {
+        if let Some(sender) = self.sender(name) {
+            return sender;
+        }
+
+        let (sender, receiver) = command::stream();
+        self.channels.push(Channel {
+            name: name.to_string(),
+            sender: sender.clone(),
+            receiver: receiver
+        });
+        sender
+    }
+
+    /// Returns a sender to a channel if it exists.
+    pub fn sender(&self, name: &str) -> Option<CommandSender<C>> {
+        self.channels.iter().find(|channel| channel.name.as_slice() == name)
+                            .map(|channel| channel.sender.clone())
+    }
+
+    /// Returns a sender to the default channel.
+    pub fn default_sender(&self) -> CommandSender<C> {
+        self.channels.iter().find(|channel| channel.name.as_slice() == DEFAULT_CHANNEL)
+                            .map(|channel| channel.sender.clone())
+                            .unwrap()
+    }
+
+    /// Sets the processing order of the channels.
+    ///
+    /// The given channels come first, in the given order,
+    /// the others keeping their relative order after them.
+    /// Panics if a given channel doesn't exist.
+    pub fn set_order(&mut self, names: &[&str]) {
+        let mut ordered = Vec::with_capacity(self.channels.len());
+        for name in names.iter() {
+            match self.channels.iter().position(|channel| channel.name.as_slice() == *name) {
+                Some(index) => ordered.push(self.channels.remove(index)),
+                None => panic!("There is no command channel named {}", name)
+            }
+        }
+        ordered.extend(self.channels.drain());
+        self.channels = ordered;
+    }
+
+    /// Returns the channel names, in processing order.
+    pub fn names(&self) -> Vec<String> {
+        self.channels.iter().map(|channel| channel.name.clone()).collect()
+    }
+
+    /// Returns the number of channels.
+    pub fn len(&self) -> usize {
+        self.channels.len()
+    }
+
+    /// Returns the receiver of a channel if it exists.
+    pub fn receiver_mut(&mut self, name: &str) -> Option<&mut CommandReceiver<C>> {
+        self.channels.iter_mut().find(|channel| channel.name.as_slice() == name)
+                                .map(|channel| &mut channel.receiver)
+    }
+
+    /// Returns the receivers, in processing order.
+    pub fn receivers_mut(&mut self) -> Vec<&mut CommandReceiver<C>> {
+        self.channels.iter_mut().map(|channel| &mut channel.receiver).collect()
+    }
+
+    /// Returns the receivers along with the channel names, in processing order.
+    pub fn named_receivers_mut(&mut self) -> Vec<(&str, &mut CommandReceiver<C>)> {
+        self.channels.iter_mut().map(|channel| {
+            let Channel { ref name, ref mut receiver, .. } = *channel;
+            (name.as_slice(), receiver)
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Channels, DEFAULT_CHANNEL};
+
+    #[test]
+    fn add() {
+        let mut channels = Channels::<usize>::new();
+        let mut spawn = channels.add("spawn");
+        let mut again = channels.add("spawn");
+        assert_eq!(channels.len(), 2);
+
+        spawn.send(0).ok().unwrap();
+        again.send(1).ok().unwrap();
+        let receiver = channels.receiver_mut("spawn").unwrap();
+        assert_eq!(receiver.recv(), Some(0));
+        assert_eq!(receiver.recv(), Some(1));
+    }
+
+    #[test]
+    fn senders() {
+        let mut channels = Channels::<usize>::new();
+        channels.add("spawn");
+
+        assert!(channels.sender("spawn").is_some());
+        assert!(channels.sender("unknown").is_none());
+        assert!(channels.receiver_mut("unknown").is_none());
+
+        channels.default_sender().send(0).ok().unwrap();
+        assert_eq!(channels.receiver_mut(DEFAULT_CHANNEL).unwrap().len(), 1);
+        assert_eq!(channels.receiver_mut("spawn").unwrap().len(), 0);
+    }
+
+    #[test]
+    fn set_order() {
+        let mut channels = Channels::<usize>::new();
+        channels.add("a");
+        channels.add("b");
+        channels.add("c");
+
+        channels.set_order(&["c", "a"]);
+        assert_eq!(channels.names(), vec!["c".to_string(), "a".to_string(),
+                                          DEFAULT_CHANNEL.to_string(), "b".to_string()]);
+
+        let names: Vec<String> = channels.named_receivers_mut().into_iter()
+                                         .map(|(name, _)| name.to_string())
+                                         .collect();
+        assert_eq!(names, channels.names());
+    }
+
+    #[test]
+    #[should_fail]
+    fn set_order_unknown() {
+        let mut channels = Channels::<usize>::new();
+        channels.set_order(&["unknown"]);
+    }
+}
@@ -0,0 +1,301 @@
+//! Undo and redo of commands.
+//!
+//! An editor can send undoable commands through a stream,
+//! the `History` wrapping the receiver to keep track of what was run:
+//!
+//! ````ignore
+//! type EditorCommand = Box<for<'a> UndoableCommand<Args = &'a mut Space>>;
+//!
+//! let (mut sender, receiver) = command::stream::<EditorCommand>();
+//! let mut history = History::new(receiver, 100);
+//!
+//! // Both commands will be undone at once.
+//! history.begin();
+//! sender.send(Box::new(MoveEntity { entity: rock, offset: [1., 0.] }));
+//! sender.send(Box::new(SetGroup { entity: rock, group: "props".to_string() }));
+//! history.process(|command| command.run(&mut space));
+//! history.commit();
+//!
+//! history.undo(|command| command.undo(&mut space));
+//! ````
+
+use std::collections::RingBuf;
+
+use command::{Command, CommandReceiver};
+
+/// Represents any command that can be reverted.
+pub trait UndoableCommand: Command {
+    /// Reverts the effects of the last run of the command.
+    fn undo(&mut self, args: <Self as Command>::Args);
+}
+
+/// Commands which are undone and redone together.
+type Transaction<C> = Vec<C>;
+
+/// Keeps track of the commands received from a stream, to undo and redo them.
+///
+/// Each processed command is a transaction on its own,
+/// unless grouped with others between `begin` and `commit`.
+pub struct History<C> {
+    receiver: CommandReceiver<C>,
+    undo_stack: RingBuf<Transaction<C>>,
+    redo_stack: Vec<Transaction<C>>,
+    open: Option<Transaction<C>>,
+    limit: usize
+}
+
+impl<C> History<C> {
+    /// Creates an empty `History` around a receiver,
+    /// keeping at most `limit` transactions to undo.
+    pub fn new(receiver: CommandReceiver<C>, limit: usize) -> History<C> {
+        History {
+            receiver: receiver,
+            undo_stack: RingBuf::new(),
+            redo_stack: Vec::new(),
+            open: None,
+            limit: limit
+        }
+    }
+
+    /// Opens a transaction, grouping the next processed commands until `commit`.
+    ///
+    /// Does nothing if a transaction is already open.
+    pub fn begin(&mut self) {
+        if self.open.is_none() {
+            self.open = Some(Vec::new());
+        }
+    }
+
+    /// Closes the open transaction, if any.
+    pub fn commit(&mut self) {
+        if let Some(transaction) = self.open.take() {
+            self.push(transaction);
+        }
+    }
+
+    /// Runs the pending commands with `run`, recording them in the history.
+    ///
+    /// The commands which were undone can't be redone anymore.
+    pub fn process<F>(&mut self, mut run: F)
+        where F: FnMut(&mut C)
+    {
+        while let Some(mut command) = self.receiver.recv() {
+            run(&mut command);
+            self.redo_stack.clear();
+
+            if self.open.is_some() {
+                self.open.as_mut().unwrap().push(command);
+            } else {
+                self.push(vec![command]);
+            }
+        }
+    }
+
+    /// Undoes the last transaction with `undo`, its commands being undone in reverse order.
+    ///
+    /// An open transaction is committed first.
+    /// Returns `false` if there was nothing to undo.
+    pub fn undo<F>(&mut self, mut undo: F) -> bool
+        where F: FnMut(&mut C)
+    {
+        self.commit();
+        match self.undo_stack.pop_back() {
+            Some(mut transaction) => {
+                for command in transaction.iter_mut().rev() {
+                    undo(command);
+                }
+                self.redo_stack.push(transaction);
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Redoes the last undone transaction with `run`.
+    ///
+    /// Returns `false` if there was nothing to redo.
+    pub fn redo<F>(&mut self, mut run: F) -> bool
+        where F: FnMut(&mut C)
+    {
+        match self.redo_stack.pop() {
+            Some(mut transaction) => {
+                for command in transaction.iter_mut() {
+                    run(command);
+                }
+                self.push(transaction);
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Returns `true` if there is a transaction to undo.
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty() || self.open.as_ref().map_or(false, |open| !open.is_empty())
+    }
+
+    /// Returns `true` if there is a transaction to redo.
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    /// Forgets every transaction.
+    pub fn clear(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.open = None;
+    }
+
+    /// Pushes a transaction on the undo stack, dropping the oldest ones over the limit.
+    ///
+    /// Empty transactions are ignored.
+    fn push(&mut self, transaction: Transaction<C>) {
+        if transaction.is_empty() {
+            return;
+        }
+
+        self.undo_stack.push_back(transaction);
+        while self.undo_stack.len() > self.limit {
+            self.undo_stack.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use std::cell::RefCell;
+
+    use command::{self, Command, CommandSender};
+    use super::{UndoableCommand, History};
+
+    type Log = Rc<RefCell<Vec<usize>>>;
+
+    /// Pushes a value on a log, and pops it when undone.
+    struct Push {
+        value: usize,
+        log: Log
+    }
+
+    impl Command for Push {
+        type Args = ();
+        fn run(&mut self, _: ()) {
+            self.log.borrow_mut().push(self.value);
+        }
+    }
+
+    impl UndoableCommand for Push {
+        fn undo(&mut self, _: ()) {
+            let popped = self.log.borrow_mut().pop();
+            assert_eq!(popped, Some(self.value));
+        }
+    }
+
+    /// Creates a history with the given limit, its sender and log.
+    fn setup(limit: usize) -> (History<Push>, CommandSender<Push>, Log) {
+        let (sender, receiver) = command::stream();
+        (History::new(receiver, limit), sender, Rc::new(RefCell::new(Vec::new())))
+    }
+
+    /// Sends a `Push` command.
+    fn push(sender: &mut CommandSender<Push>, log: &Log, value: usize) {
+        sender.send(Push { value: value, log: log.clone() }).ok().unwrap();
+    }
+
+    #[test]
+    fn transactions() {
+        let (mut history, mut sender, log) = setup(10);
+
+        history.begin();
+        push(&mut sender, &log, 1);
+        push(&mut sender, &log, 2);
+        history.process(|command| command.run(()));
+        history.commit();
+        push(&mut sender, &log, 3);
+        history.process(|command| command.run(()));
+        assert_eq!(*log.borrow(), vec![1, 2, 3]);
+
+        assert!(history.undo(|command| command.undo(())));
+        assert_eq!(*log.borrow(), vec![1, 2]);
+        assert!(history.undo(|command| command.undo(())));
+        assert!(log.borrow().is_empty());
+        assert!(!history.undo(|command| command.undo(())));
+    }
+
+    #[test]
+    fn undo_redo_order() {
+        let (mut history, mut sender, log) = setup(10);
+        push(&mut sender, &log, 1);
+        push(&mut sender, &log, 2);
+        history.process(|command| command.run(()));
+
+        history.undo(|command| command.undo(()));
+        history.undo(|command| command.undo(()));
+        assert!(!history.can_undo());
+
+        assert!(history.redo(|command| command.run(())));
+        assert_eq!(*log.borrow(), vec![1]);
+        assert!(history.redo(|command| command.run(())));
+        assert_eq!(*log.borrow(), vec![1, 2]);
+        assert!(!history.redo(|command| command.run(())));
+    }
+
+    #[test]
+    fn new_commands_clear_redo() {
+        let (mut history, mut sender, log) = setup(10);
+        push(&mut sender, &log, 1);
+        history.process(|command| command.run(()));
+        history.undo(|command| command.undo(()));
+        assert!(history.can_redo());
+
+        push(&mut sender, &log, 2);
+        history.process(|command| command.run(()));
+        assert!(!history.can_redo());
+        assert_eq!(*log.borrow(), vec![2]);
+    }
+
+    #[test]
+    fn limit() {
+        let (mut history, mut sender, log) = setup(2);
+        push(&mut sender, &log, 1);
+        push(&mut sender, &log, 2);
+        push(&mut sender, &log, 3);
+        history.process(|command| command.run(()));
+
+        assert!(history.undo(|command| command.undo(())));
+        assert!(history.undo(|command| command.undo(())));
+        assert!(!history.undo(|command| command.undo(())));
+        assert_eq!(*log.borrow(), vec![1]);
+    }
+
+    #[test]
+    fn zero_limit() {
+        let (mut history, mut sender, log) = setup(0);
+        push(&mut sender, &log, 1);
+        history.process(|command| command.run(()));
+
+        assert!(!history.can_undo());
+        assert!(!history.undo(|command| command.undo(())));
+        assert_eq!(*log.borrow(), vec![1]);
+    }
+
+    #[test]
+    fn undo_open_transaction() {
+        let (mut history, mut sender, log) = setup(10);
+        history.begin();
+        push(&mut sender, &log, 1);
+        push(&mut sender, &log, 2);
+        history.process(|command| command.run(()));
+
+        assert!(history.can_undo());
+        assert!(history.undo(|command| command.undo(())));
+        assert!(log.borrow().is_empty());
+
+        // The transaction was committed, later commands aren't part of it.
+        push(&mut sender, &log, 3);
+        history.process(|command| command.run(()));
+        assert!(history.undo(|command| command.undo(())));
+        assert!(log.borrow().is_empty());
+        assert!(!history.can_undo());
+    }
+}
@@ -0,0 +1,1248 @@
+//! The command related features.
+//!
+//! ## Scheduled commands
+//!
+//! Commands can be delayed, either in seconds or until a given fixed update tick,
+//! and can be repeated:
+//!
+//! ````ignore
+//! sender.send_after(2., open_door).ok().unwrap();
+//! sender.send_at_tick(500, respawn).ok().unwrap();
+//! let handle = sender.send_every(0.5, spawn_wave).ok().unwrap();
+//! // ...
+//! handle.cancel();
+//! ````
+//!
+//! The receiver clock is advanced by the updates and fixed updates of the `Space`
+//! and of its `SystemMapper`, for space and inter-system commands alike.
+//!
+//! ## Bounded streams
+//!
+//! A stream can be bounded, with a policy telling what to do when it's full:
+//!
+//! ````ignore
+//! let (sender, receiver) = command::bounded_stream(Some(256), OverflowPolicy::DropOldest);
+//! ````
+//!
+//! Sending fails when the receiver was dropped or when a full stream rejects commands,
+//! and the streams count the sent and dropped commands, see `StreamStats`.
+//!
+//! ## Priorities and channels
+//!
+//! The pending commands of a stream are run by decreasing priority:
+//!
+//! ````ignore
+//! sender.send_with_priority(Priority::High, pause);
+//! ````
+//!
+//! A `Space` also receives commands from several named channels processed in a configurable order,
+//! see the [channel](channel/index.html) module.
+//!
+//! ## Undo and redo
+//!
+//! Commands implementing `UndoableCommand` can be reverted,
+//! see the [history](history/index.html) module.
+//!
+//! ## Command results
+//!
+//! A command can give back a result through a `Replier`, which is read from the `Reply`:
+//!
+//! ````ignore
+//! let reply = sender.send_with_reply(|replier| FromFn::from_fn(move |space: &mut Space| {
+//!     replier.send(space.em.create_entity());
+//! })).unwrap();
+//! space.update(dt);
+//! let entity = reply.try_take().unwrap();
+//! ````
+
+use std::rc::{self, Rc};
+use std::cell::RefCell;
+use std::sync::{self, Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::{RingBuf, BinaryHeap};
+use std::cmp;
+use std::fmt;
+use std::mem;
+use std::num::Float;
+
+pub use self::history::{UndoableCommand, History};
+pub use self::channel::{Channels, DEFAULT_CHANNEL};
+
+pub mod history;
+pub mod channel;
+
+/// Represents any command that can be runned.
+pub trait Command: 'static {
+    type Args;
+
+    /// Runs the command.
+    fn run(&mut self, args: <Self as Command>::Args);
+
+    /// Encodes the command, so that it can be recorded and replayed.
+    ///
+    /// Commands which can't be encoded return `None`, which is the default.
+    /// See the [record](../record/index.html) module.
+    fn encode(&self) -> Option<Encoded> {
+        None
+    }
+}
+
+/// An encoded command, identified by the name its decoder is registered with.
+///
+/// The data can be any string, it's escaped when the command is saved.
+#[derive(Clone, PartialEq, Show)]
+pub struct Encoded {
+    pub name: String,
+    pub data: String
+}
+
+impl Encoded {
+    /// Creates an encoded command.
+    ///
+    /// Panics if the name is empty or contains whitespace.
+    pub fn new(name: &str, data: String) -> Encoded {
+        if name.is_empty() || name.chars().any(|c| c.is_whitespace()) {
+            panic!("The name of an encoded command can't be empty or contain whitespace: {:?}",
+                   name);
+        }
+
+        Encoded {
+            name: name.to_string(),
+            data: data
+        }
+    }
+}
+
+/// Converts a closure into a command.
+///
+/// This is what allows sending closures with `CommandSender::send_fn`.
+pub trait FromFn<F> {
+    /// Wraps the closure into a command.
+    fn from_fn(func: F) -> Self;
+}
+
+/// The error returned when a command can't be sent, giving the command back.
+pub enum SendError<C> {
+    /// The receiver was dropped.
+    Disconnected(C),
+    /// The stream is full and rejects new commands.
+    Full(C)
+}
+
+impl<C> SendError<C> {
+    /// Returns the command which couldn't be sent.
+    pub fn into_inner(self) -> C {
+        match self {
+            SendError::Disconnected(command) | SendError::Full(command) => command
+        }
+    }
+}
+
+impl<C> fmt::Show for SendError<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SendError::Disconnected(_) => write!(f, "the command receiver was dropped"),
+            SendError::Full(_) => write!(f, "the command stream is full")
+        }
+    }
+}
+
+/// The priority of a command within its stream.
+///
+/// The pending commands are run by decreasing priority,
+/// in insertion order within a priority.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Show)]
+pub enum Priority {
+    High,
+    Normal,
+    Low
+}
+
+/// The number of priority levels.
+const PRIORITY_COUNT: usize = 3;
+
+/// What a bounded stream does when a command is sent while it's full.
+#[derive(Copy, Clone, PartialEq, Eq, Show)]
+pub enum OverflowPolicy {
+    /// The new command is rejected.
+    Reject,
+    /// The oldest pending command of the lowest priority is dropped
+    /// to make room for the new one.
+    ///
+    /// Scheduled commands are never dropped, so without any pending command
+    /// the new command is rejected instead.
+    DropOldest,
+    /// The stream grows beyond its capacity.
+    Grow
+}
+
+/// The counters of a command stream, for debugging purposes.
+#[derive(Copy, Clone, PartialEq, Eq, Show)]
+pub struct StreamStats {
+    /// The number of commands accepted by the stream.
+    pub sent: usize,
+    /// The number of commands rejected or dropped because the stream was full.
+    pub dropped: usize,
+    /// The number of pending commands.
+    pub pending: usize,
+    /// The number of scheduled commands, including the cancelled ones which aren't due yet.
+    pub scheduled: usize,
+    /// The largest number of pending and scheduled commands so far.
+    pub peak: usize
+}
+
+/// Creates a new command stream.
+///
+/// Note that this is not thread safe, see `sync_stream`.
+pub fn stream<C>() -> (CommandSender<C>, CommandReceiver<C>) {
+    bounded_stream(None, OverflowPolicy::Grow)
+}
+
+/// Creates a new command stream holding at most `capacity` pending and scheduled commands,
+/// `policy` telling what to do when it's full.
+///
+/// Note that this is not thread safe, see `sync_bounded_stream`.
+pub fn bounded_stream<C>(capacity: Option<usize>, policy: OverflowPolicy)
+                         -> (CommandSender<C>, CommandReceiver<C>) {
+    shared_stream(capacity, policy)
+}
+
+/// Creates a new thread safe command stream.
+///
+/// The sender can be sent to other threads, provided that the commands can.
+pub fn sync_stream<C>() -> (SyncCommandSender<C>, SyncCommandReceiver<C>)
+    where C: Send
+{
+    sync_bounded_stream(None, OverflowPolicy::Grow)
+}
+
+/// Creates a new thread safe command stream holding at most `capacity` pending
+/// and scheduled commands,
+/// `policy` telling what to do when it's full.
+pub fn sync_bounded_stream<C>(capacity: Option<usize>, policy: OverflowPolicy)
+                              -> (SyncCommandSender<C>, SyncCommandReceiver<C>)
+    where C: Send
+{
+    shared_stream(capacity, policy)
+}
+
+/// Creates a new command stream whose buffer is shared as a `B`.
+fn shared_stream<C, B>(capacity: Option<usize>, policy: OverflowPolicy)
+                       -> (CommandSender<C, B>, CommandReceiver<C, B>)
+    where B: SharedBuffer<C>
+{
+    let buffer: B = SharedBuffer::share(CommandBuffer::new(capacity, policy));
+
+    (CommandSender { buffer: buffer.weak() }, CommandReceiver { buffer: buffer })
+}
+
+/// Sends commands to a `CommandReceiver`.
+///
+/// You can have multiple senders per stream using the clone method.
+/// The buffer of the stream is shared as a `B`, see `SyncCommandSender` for threads.
+pub struct CommandSender<C, B = LocalBuffer<C>>
+    where B: SharedBuffer<C>
+{
+    buffer: <B as SharedBuffer<C>>::Weak
+}
+
+/// Sends commands to a `SyncCommandReceiver`, possibly from other threads.
+pub type SyncCommandSender<C> = CommandSender<C, SyncBuffer<C>>;
+
+impl<C, B> Clone for CommandSender<C, B>
+    where B: SharedBuffer<C>
+{
+    fn clone(&self) -> CommandSender<C, B> {
+        CommandSender {
+            buffer: self.buffer.clone()
+        }
+    }
+}
+
+impl<C, B> CommandSender<C, B>
+    where B: SharedBuffer<C>
+{
+    /// Sends a command to the linked `CommandReceiver`, with the normal priority.
+    ///
+    /// Fails if the receiver was dropped or if the stream is full and rejects commands.
+    pub fn send(&mut self, command: C) -> Result<(), SendError<C>> {
+        self.send_with_priority(Priority::Normal, command)
+    }
+
+    /// Sends a command to the linked `CommandReceiver`, with the given priority.
+    ///
+    /// Fails if the receiver was dropped or if the stream is full and rejects commands.
+    pub fn send_with_priority(&mut self, priority: Priority, command: C)
+                              -> Result<(), SendError<C>> {
+        match self.upgrade() {
+            Some(buffer) => buffer.with(|buffer| buffer.push(priority, command)),
+            None => Err(SendError::Disconnected(command))
+        }
+    }
+
+    /// Sends a command built by `build`, which is given a `Replier`
+    /// to send back its result through the returned `Reply`.
+    ///
+    /// Fails if the receiver was dropped or if the stream is full and rejects commands.
+    pub fn send_with_reply<T, F>(&mut self, build: F) -> Result<Reply<T>, SendError<C>>
+        where F: FnOnce(Replier<T>) -> C
+    {
+        let (replier, reply) = reply();
+        self.send(build(replier)).map(|_| reply)
+    }
+
+    /// Returns `true` if the linked `CommandReceiver` still exists.
+    pub fn is_connected(&self) -> bool {
+        self.upgrade().is_some()
+    }
+
+    /// Returns the counters of the stream, or `None` if the receiver was dropped.
+    pub fn stats(&self) -> Option<StreamStats> {
+        self.upgrade().map(|buffer| buffer.with(|buffer| buffer.stats()))
+    }
+
+    /// Sends a command to be run once `delay` seconds have elapsed.
+    ///
+    /// A negative delay is handled as no delay.
+    /// Panics if the delay isn't finite.
+    /// Fails if the receiver was dropped or if the stream is full and rejects commands.
+    pub fn send_after(&mut self, delay: f32, command: C) -> Result<CommandHandle, SendError<C>> {
+        self.schedule(Schedule::after(delay), command)
+    }
+
+    /// Sends a command to be run at the given fixed update tick.
+    ///
+    /// Ticks are counted by the receiver, the first fixed update being the tick 1.
+    /// If the tick already passed, the command is run at the next fixed update.
+    /// Fails if the receiver was dropped or if the stream is full and rejects commands.
+    pub fn send_at_tick(&mut self, tick: usize, command: C)
+                        -> Result<CommandHandle, SendError<C>> {
+        self.schedule(Schedule::at_tick(tick), command)
+    }
+
+    /// Sends a command to be run every `interval` seconds, until cancelled.
+    ///
+    /// The first run occurs after `interval` seconds.
+    /// Panics if the interval isn't positive.
+    /// Fails if the receiver was dropped or if the stream is full and rejects commands.
+    pub fn send_every(&mut self, interval: f32, command: C)
+                      -> Result<CommandHandle, SendError<C>> {
+        self.schedule(Schedule::every(interval), command)
+    }
+
+    /// Sends a command to be run every `interval` fixed update ticks, until cancelled.
+    ///
+    /// The first run occurs after `interval` ticks.
+    /// Panics if the interval is zero.
+    /// Fails if the receiver was dropped or if the stream is full and rejects commands.
+    pub fn send_every_ticks(&mut self, interval: usize, command: C)
+                            -> Result<CommandHandle, SendError<C>> {
+        self.schedule(Schedule::every_ticks(interval), command)
+    }
+
+    /// Schedules a command relative to the receiver clock.
+    fn schedule(&mut self, schedule: Schedule, command: C) -> Result<CommandHandle, SendError<C>> {
+        let handle = CommandHandle::new();
+        match self.upgrade() {
+            Some(buffer) => buffer.with(|buffer| {
+                buffer.schedule(schedule, command, handle.clone())
+            }).map(|_| handle),
+            None => Err(SendError::Disconnected(command))
+        }
+    }
+
+    /// Sends a closure as a command to the linked `CommandReceiver`.
+    ///
+    /// ````ignore
+    /// sender.send_fn(move |em: &mut EntityMapper, cm: &mut ComponentMapper| {
+    ///     em.remove_entity(bullet);
+    /// });
+    /// ````
+    ///
+    /// Fails if the receiver was dropped or if the stream is full and rejects commands.
+    pub fn send_fn<F>(&mut self, func: F) -> Result<(), SendError<C>>
+        where C: FromFn<F>
+    {
+        self.send(FromFn::from_fn(func))
+    }
+
+    /// Returns the buffer of the stream, or `None` if the receiver was dropped.
+    fn upgrade(&self) -> Option<B> {
+        SharedBuffer::upgrade(&self.buffer)
+    }
+}
+
+/// Receives commands from `CommandSender`s.
+///
+/// You can only have one receiver per stream.
+pub struct CommandReceiver<C, B = LocalBuffer<C>>
+    where B: SharedBuffer<C>
+{
+    buffer: B
+}
+
+/// Receives commands from `SyncCommandSender`s.
+pub type SyncCommandReceiver<C> = CommandReceiver<C, SyncBuffer<C>>;
+
+impl<C, B> CommandReceiver<C, B>
+    where B: SharedBuffer<C>
+{
+    /// Retrieves a command from the stream.
+    ///
+    /// The commands are retrieved by decreasing priority,
+    /// the insertion order being preserved within a priority.
+    ///
+    /// Returns `None` if there is no more commands to retrieve.
+    pub fn recv(&mut self) -> Option<C> {
+        self.recv_flagged().map(|(command, _)| command)
+    }
+
+    /// Retrieves a command from the stream like `recv`,
+    /// telling whether it was sent during a frame.
+    pub fn recv_flagged(&mut self) -> Option<(C, bool)> {
+        self.buffer.with(|buffer| buffer.pop())
+    }
+
+    /// Flags the commands sent from now on, scheduled ones included,
+    /// as sent during a frame or not.
+    ///
+    /// This is done by `Space` while it updates, so that the commands sent
+    /// by other commands or by systems can be told apart when recording.
+    pub fn set_in_frame(&mut self, in_frame: bool) {
+        self.buffer.with(|buffer| buffer.in_frame = in_frame);
+    }
+
+    /// Returns the number of pending commands.
+    ///
+    /// Scheduled commands are not counted.
+    pub fn len(&self) -> usize {
+        self.buffer.with(|buffer| buffer.len())
+    }
+
+    /// Returns the counters of the stream.
+    pub fn stats(&self) -> StreamStats {
+        self.buffer.with(|buffer| buffer.stats())
+    }
+
+    /// Advances the clock of scheduled commands by `dt` seconds.
+    ///
+    /// This is done by `Space::update`.
+    pub fn advance_time(&mut self, dt: f32) {
+        self.buffer.with(|buffer| buffer.advance(Timing::Seconds(dt as f64)));
+    }
+
+    /// Advances the clock of scheduled commands by one tick.
+    ///
+    /// This is done by `Space::fixed_update`.
+    pub fn advance_tick(&mut self) {
+        self.buffer.with(|buffer| buffer.advance(Timing::Ticks(1)));
+    }
+
+    /// Retrieves the next scheduled command which is due.
+    ///
+    /// Only the commands scheduled before the last clock advance are considered,
+    /// so that a command scheduling another one without delay can't loop forever.
+    /// Repeating commands must be given back with `reschedule` after being run.
+    ///
+    /// Returns `None` if there is no more due commands.
+    pub fn recv_due(&mut self) -> Option<Scheduled<C>> {
+        self.buffer.with(|buffer| buffer.pop_due())
+    }
+
+    /// Gives back a scheduled command after it was run.
+    ///
+    /// Repeating commands which weren't cancelled are scheduled again.
+    pub fn reschedule(&mut self, scheduled: Scheduled<C>) {
+        self.buffer.with(|buffer| buffer.reschedule(scheduled));
+    }
+}
+
+/// Creates a new reply channel.
+///
+/// The `Replier` is given to a command and the `Reply` kept by the sender.
+pub fn reply<T>() -> (Replier<T>, Reply<T>) {
+    let slot = Arc::new(Mutex::new(None));
+
+    (Replier(slot.clone()), Reply(slot))
+}
+
+/// Sends the result of a command to its `Reply`.
+pub struct Replier<T>(Arc<Mutex<Option<T>>>);
+
+impl<T> Replier<T> {
+    /// Sends the result, replacing any previous one which wasn't taken.
+    pub fn send(&self, value: T) {
+        *self.0.lock().unwrap() = Some(value);
+    }
+}
+
+/// Receives the result of a command.
+pub struct Reply<T>(Arc<Mutex<Option<T>>>);
+
+impl<T> Reply<T> {
+    /// Takes the result if the command sent it.
+    ///
+    /// Returns `None` if the command wasn't run yet or the result was already taken.
+    pub fn try_take(&self) -> Option<T> {
+        self.0.lock().unwrap().take()
+    }
+
+    /// Returns `true` if a result is waiting to be taken.
+    pub fn is_ready(&self) -> bool {
+        self.0.lock().unwrap().is_some()
+    }
+}
+
+/// A handle to cancel a scheduled command.
+#[derive(Clone)]
+pub struct CommandHandle {
+    cancelled: Arc<AtomicBool>
+}
+
+impl CommandHandle {
+    /// Creates a new handle.
+    fn new() -> CommandHandle {
+        CommandHandle {
+            cancelled: Arc::new(AtomicBool::new(false))
+        }
+    }
+
+    /// Cancels the command, which won't be run anymore.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns `true` if the command was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// A point in time, or a duration, either in seconds or in ticks.
+///
+/// Seconds are accumulated as `f64`, so that the clock doesn't lose precision
+/// after running for hours.
+#[derive(Copy, Clone, PartialEq, PartialOrd, Show)]
+enum Timing {
+    Seconds(f64),
+    Ticks(usize)
+}
+
+impl Timing {
+    /// Returns the timing after the given delay, which must be of the same kind.
+    fn after(&self, delay: Timing) -> Timing {
+        match (*self, delay) {
+            (Timing::Seconds(at), Timing::Seconds(delay)) => Timing::Seconds(at + delay),
+            (Timing::Ticks(at), Timing::Ticks(delay)) => Timing::Ticks(at + delay),
+            _ => panic!("Mismatching timings {:?} and {:?}", *self, delay)
+        }
+    }
+}
+
+/// When to run a command sent to be scheduled.
+#[derive(Copy, Clone, Show)]
+enum Schedule {
+    /// After a delay relative to the receiver clock, possibly repeating.
+    After(Timing, Option<Timing>),
+    /// At a given time of the receiver clock.
+    At(Timing)
+}
+
+impl Schedule {
+    /// Runs once after `delay` seconds, a negative delay being clamped to zero.
+    ///
+    /// Panics if the delay isn't finite.
+    fn after(delay: f32) -> Schedule {
+        assert!(delay.is_finite(), "the delay of a command must be finite");
+        Schedule::After(Timing::Seconds(delay.max(0.) as f64), None)
+    }
+
+    /// Runs once at the given tick.
+    fn at_tick(tick: usize) -> Schedule {
+        Schedule::At(Timing::Ticks(tick))
+    }
+
+    /// Runs every `interval` seconds.
+    ///
+    /// Panics if the interval isn't positive.
+    fn every(interval: f32) -> Schedule {
+        assert!(interval > 0., "the interval of a repeating command must be positive");
+        let interval = Timing::Seconds(interval as f64);
+        Schedule::After(interval, Some(interval))
+    }
+
+    /// Runs every `interval` ticks.
+    ///
+    /// Panics if the interval is zero.
+    fn every_ticks(interval: usize) -> Schedule {
+        assert!(interval > 0, "the interval of a repeating command must be positive");
+        let interval = Timing::Ticks(interval);
+        Schedule::After(interval, Some(interval))
+    }
+}
+
+/// A scheduled command.
+pub struct Scheduled<C> {
+    /// The command to run.
+    pub command: C,
+    at: Timing,
+    every: Option<Timing>,
+    sequence: usize,
+    in_frame: bool,
+    handle: CommandHandle
+}
+
+impl<C> Scheduled<C> {
+    /// Returns `true` if the command was scheduled during a frame.
+    ///
+    /// See `CommandReceiver::set_in_frame`.
+    pub fn is_in_frame(&self) -> bool {
+        self.in_frame
+    }
+
+    /// Returns `true` if the command was cancelled since it was retrieved.
+    pub fn is_cancelled(&self) -> bool {
+        self.handle.is_cancelled()
+    }
+}
+
+impl<C> PartialEq for Scheduled<C> {
+    fn eq(&self, other: &Scheduled<C>) -> bool {
+        self.sequence == other.sequence
+    }
+}
+
+impl<C> Eq for Scheduled<C> {}
+
+impl<C> PartialOrd for Scheduled<C> {
+    fn partial_cmp(&self, other: &Scheduled<C>) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<C> Ord for Scheduled<C> {
+    /// The earliest command is the greatest, for the `BinaryHeap` to be a min-heap.
+    /// Commands scheduled at the same time keep their insertion order.
+    fn cmp(&self, other: &Scheduled<C>) -> cmp::Ordering {
+        match other.at.partial_cmp(&self.at) {
+            Some(cmp::Ordering::Equal) | None => other.sequence.cmp(&self.sequence),
+            Some(ordering) => ordering
+        }
+    }
+}
+
+/// The buffer of a command stream, shared by its senders and its receiver.
+///
+/// A `LocalBuffer` is used by the streams of a thread, and a `SyncBuffer` by thread safe streams.
+pub trait SharedBuffer<C>: Sized {
+    /// The reference held by the senders, which doesn't keep the buffer alive.
+    type Weak: Clone;
+
+    /// Shares a new buffer.
+    fn share(buffer: CommandBuffer<C>) -> Self;
+
+    /// Returns a weak reference to the buffer.
+    fn weak(&self) -> <Self as SharedBuffer<C>>::Weak;
+
+    /// Returns the buffer if it still exists.
+    fn upgrade(weak: &<Self as SharedBuffer<C>>::Weak) -> Option<Self>;
+
+    /// Calls `func` with the buffer.
+    fn with<T, F>(&self, func: F) -> T
+        where F: FnOnce(&mut CommandBuffer<C>) -> T;
+}
+
+/// The buffer of a stream which isn't thread safe.
+pub type LocalBuffer<C> = Rc<RefCell<CommandBuffer<C>>>;
+
+/// The buffer of a thread safe stream.
+pub type SyncBuffer<C> = Arc<Mutex<CommandBuffer<C>>>;
+
+impl<C> SharedBuffer<C> for LocalBuffer<C> {
+    type Weak = rc::Weak<RefCell<CommandBuffer<C>>>;
+
+    fn share(buffer: CommandBuffer<C>) -> LocalBuffer<C> {
+        Rc::new(RefCell::new(buffer))
+    }
+
+    fn weak(&self) -> rc::Weak<RefCell<CommandBuffer<C>>> {
+        self.downgrade()
+    }
+
+    fn upgrade(weak: &rc::Weak<RefCell<CommandBuffer<C>>>) -> Option<LocalBuffer<C>> {
+        weak.upgrade()
+    }
+
+    fn with<T, F>(&self, func: F) -> T
+        where F: FnOnce(&mut CommandBuffer<C>) -> T
+    {
+        func(&mut *self.borrow_mut())
+    }
+}
+
+impl<C> SharedBuffer<C> for SyncBuffer<C>
+    where C: Send
+{
+    type Weak = sync::Weak<Mutex<CommandBuffer<C>>>;
+
+    fn share(buffer: CommandBuffer<C>) -> SyncBuffer<C> {
+        Arc::new(Mutex::new(buffer))
+    }
+
+    fn weak(&self) -> sync::Weak<Mutex<CommandBuffer<C>>> {
+        self.downgrade()
+    }
+
+    fn upgrade(weak: &sync::Weak<Mutex<CommandBuffer<C>>>) -> Option<SyncBuffer<C>> {
+        weak.upgrade()
+    }
+
+    fn with<T, F>(&self, func: F) -> T
+        where F: FnOnce(&mut CommandBuffer<C>) -> T
+    {
+        func(&mut *self.lock().unwrap())
+    }
+}
+
+/// A simple buffer of commands using a `RingBuf` per priority, possibly bounded,
+/// and priority queues of scheduled commands.
+///
+/// Each command is flagged with whether it was sent during a frame.
+pub struct CommandBuffer<C> {
+    commands: Vec<RingBuf<(C, bool)>>,
+    capacity: Option<usize>,
+    policy: OverflowPolicy,
+    sent: usize,
+    dropped: usize,
+    peak: usize,
+    timed: BinaryHeap<Scheduled<C>>,
+    ticked: BinaryHeap<Scheduled<C>>,
+    time: f64,
+    tick: usize,
+    next_sequence: usize,
+    due_sequence: usize,
+    in_frame: bool
+}
+
+impl<C> CommandBuffer<C> {
+    /// Creates an empty `CommandBuffer`.
+    fn new(capacity: Option<usize>, policy: OverflowPolicy) -> CommandBuffer<C> {
+        CommandBuffer {
+            commands: range(0, PRIORITY_COUNT).map(|_| RingBuf::new()).collect(),
+            capacity: capacity,
+            policy: policy,
+            sent: 0,
+            dropped: 0,
+            peak: 0,
+            timed: BinaryHeap::new(),
+            ticked: BinaryHeap::new(),
+            time: 0.,
+            tick: 0,
+            next_sequence: 0,
+            due_sequence: 0,
+            in_frame: false
+        }
+    }
+
+    /// Returns the current time of the clock of the same kind as `timing`.
+    fn now(&self, timing: Timing) -> Timing {
+        match timing {
+            Timing::Seconds(_) => Timing::Seconds(self.time),
+            Timing::Ticks(_) => Timing::Ticks(self.tick)
+        }
+    }
+
+    /// Schedules a new command, relative to the clock for a delay.
+    ///
+    /// If the buffer is full, the overflow policy is applied.
+    fn schedule(&mut self, schedule: Schedule, command: C, handle: CommandHandle)
+                -> Result<(), SendError<C>> {
+        if !self.make_room() {
+            return Err(SendError::Full(command));
+        }
+
+        match schedule {
+            Schedule::After(delay, every) => {
+                let at = self.now(delay).after(delay);
+                self.schedule_at(at, every, command, handle);
+            },
+            Schedule::At(at) => self.schedule_at(at, None, command, handle)
+        }
+        self.count_sent();
+        Ok(())
+    }
+
+    /// Schedules a command at the given time.
+    fn schedule_at(&mut self, at: Timing, every: Option<Timing>, command: C,
+                   handle: CommandHandle) {
+        let scheduled = Scheduled {
+            command: command,
+            at: at,
+            every: every,
+            sequence: self.next_sequence,
+            in_frame: self.in_frame,
+            handle: handle
+        };
+        self.next_sequence += 1;
+
+        match at {
+            Timing::Seconds(_) => self.timed.push(scheduled),
+            Timing::Ticks(_) => self.ticked.push(scheduled)
+        }
+    }
+
+    /// Advances the clock of the same kind as `delta`.
+    fn advance(&mut self, delta: Timing) {
+        match delta {
+            Timing::Seconds(dt) => self.time += dt,
+            Timing::Ticks(ticks) => self.tick += ticks
+        }
+        self.due_sequence = self.next_sequence;
+    }
+
+    /// Pops the next due command, dropping the cancelled ones.
+    fn pop_due(&mut self) -> Option<Scheduled<C>> {
+        let CommandBuffer { ref mut timed, ref mut ticked, time, tick, due_sequence, .. } = *self;
+        CommandBuffer::pop_from(timed, Timing::Seconds(time), due_sequence)
+            .or_else(|| CommandBuffer::pop_from(ticked, Timing::Ticks(tick), due_sequence))
+    }
+
+    /// Pops the next due command of a queue, dropping the cancelled ones.
+    fn pop_from(queue: &mut BinaryHeap<Scheduled<C>>, now: Timing, due_sequence: usize)
+                -> Option<Scheduled<C>> {
+        loop {
+            let is_due = match queue.peek() {
+                Some(next) => next.at <= now && next.sequence < due_sequence,
+                None => false
+            };
+            if !is_due {
+                return None;
+            }
+
+            let next = queue.pop().unwrap();
+            if !next.handle.is_cancelled() {
+                return Some(next);
+            }
+        }
+    }
+
+    /// Schedules again a repeating command which was run.
+    ///
+    /// A command late by more than its interval isn't run several times to catch up.
+    ///
+    /// It keeps the flag it was first scheduled with.
+    fn reschedule(&mut self, scheduled: Scheduled<C>) {
+        let Scheduled { command, at, every, in_frame, handle, .. } = scheduled;
+        if let Some(interval) = every {
+            if !handle.is_cancelled() {
+                let now = self.now(at);
+                let mut next = at.after(interval);
+                if next <= now {
+                    next = now.after(interval);
+                }
+
+                let current = mem::replace(&mut self.in_frame, in_frame);
+                self.schedule_at(next, every, command, handle);
+                self.in_frame = current;
+            }
+        }
+    }
+
+    /// Pushes a new command into the buffer.
+    ///
+    /// If the buffer is full, the overflow policy is applied.
+    fn push(&mut self, priority: Priority, command: C) -> Result<(), SendError<C>> {
+        if !self.make_room() {
+            return Err(SendError::Full(command));
+        }
+
+        self.commands[priority as usize].push_back((command, self.in_frame));
+        self.count_sent();
+        Ok(())
+    }
+
+    /// Applies the overflow policy if the buffer is full.
+    ///
+    /// Returns `false` if the new command must be rejected.
+    fn make_room(&mut self) -> bool {
+        let len = self.len() + self.scheduled_len();
+        let is_full = self.capacity.map_or(false, |capacity| len >= capacity);
+        if !is_full {
+            return true;
+        }
+
+        match self.policy {
+            OverflowPolicy::Reject => {
+                self.dropped += 1;
+                false
+            },
+            OverflowPolicy::DropOldest => {
+                let is_dropped = self.commands.iter_mut().rev()
+                                              .any(|level| level.pop_front().is_some());
+                self.dropped += 1;
+                // Without any pending command, the new command is the one dropped.
+                is_dropped
+            },
+            OverflowPolicy::Grow => true
+        }
+    }
+
+    /// Counts a new command.
+    fn count_sent(&mut self) {
+        self.sent += 1;
+        self.peak = cmp::max(self.peak, self.len() + self.scheduled_len());
+    }
+
+    /// Tries to pop the command of highest priority, with its flag.
+    ///
+    /// Returns `None` if the buffer is empty.
+    fn pop(&mut self) -> Option<(C, bool)> {
+        for level in self.commands.iter_mut() {
+            if let Some(command) = level.pop_front() {
+                return Some(command);
+            }
+        }
+        None
+    }
+
+    /// Returns the number of pending commands in the buffer.
+    fn len(&self) -> usize {
+        self.commands.iter().fold(0, |len, level| len + level.len())
+    }
+
+    /// Returns the number of scheduled commands in the buffer.
+    fn scheduled_len(&self) -> usize {
+        self.timed.len() + self.ticked.len()
+    }
+
+    /// Returns the counters of the buffer.
+    fn stats(&self) -> StreamStats {
+        StreamStats {
+            sent: self.sent,
+            dropped: self.dropped,
+            pending: self.len(),
+            scheduled: self.scheduled_len(),
+            peak: self.peak
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::Float;
+
+    use super::{stream, bounded_stream, sync_stream, CommandReceiver,
+                OverflowPolicy, Priority, SendError, StreamStats};
+
+    /// Runs the due commands, rescheduling them, and returns them in order.
+    fn run_due(receiver: &mut CommandReceiver<usize>) -> Vec<usize> {
+        let mut run = Vec::new();
+        while let Some(scheduled) = receiver.recv_due() {
+            run.push(scheduled.command);
+            receiver.reschedule(scheduled);
+        }
+        run
+    }
+
+    #[test]
+    fn priorities() {
+        let (mut sender, mut receiver) = stream();
+        sender.send_with_priority(Priority::Low, 0).ok().unwrap();
+        sender.send(1).ok().unwrap();
+        sender.send_with_priority(Priority::High, 2).ok().unwrap();
+        sender.send_with_priority(Priority::Low, 3).ok().unwrap();
+        sender.send_with_priority(Priority::High, 4).ok().unwrap();
+        assert_eq!(receiver.len(), 5);
+
+        let received: Vec<usize> = range(0, 5us).filter_map(|_| receiver.recv()).collect();
+        assert_eq!(received, vec![2, 4, 1, 0, 3]);
+        assert!(receiver.recv().is_none());
+    }
+
+    #[test]
+    fn in_frame_flag() {
+        let (mut sender, mut receiver) = stream();
+        sender.send(0).ok().unwrap();
+        receiver.set_in_frame(true);
+        sender.send(1).ok().unwrap();
+        sender.send_after(0., 2).ok().unwrap();
+        receiver.set_in_frame(false);
+
+        assert_eq!(receiver.recv_flagged(), Some((0, false)));
+        assert_eq!(receiver.recv_flagged(), Some((1, true)));
+        receiver.advance_time(0.);
+        assert!(receiver.recv_due().unwrap().is_in_frame());
+    }
+
+    #[test]
+    fn sync_in_frame_flag() {
+        let (mut sender, mut receiver) = sync_stream();
+        receiver.set_in_frame(true);
+        sender.send(0).ok().unwrap();
+        sender.send_after(0., 1).ok().unwrap();
+        receiver.set_in_frame(false);
+        sender.send(2).ok().unwrap();
+
+        assert_eq!(receiver.recv_flagged(), Some((0, true)));
+        assert_eq!(receiver.recv_flagged(), Some((2, false)));
+        receiver.advance_time(0.);
+        assert!(receiver.recv_due().unwrap().is_in_frame());
+    }
+
+    #[test]
+    fn overflow_reject() {
+        let (mut sender, mut receiver) = bounded_stream(Some(2), OverflowPolicy::Reject);
+        assert!(sender.send(0).is_ok());
+        assert!(sender.send(1).is_ok());
+        match sender.send(2) {
+            Err(SendError::Full(2)) => {},
+            _ => panic!("The full stream should reject the command")
+        }
+
+        assert_eq!(receiver.recv(), Some(0));
+        assert!(sender.send(3).is_ok());
+        assert_eq!(receiver.stats(), StreamStats {
+            sent: 3, dropped: 1, pending: 2, scheduled: 0, peak: 2
+        });
+    }
+
+    #[test]
+    fn overflow_drop_oldest() {
+        let (mut sender, mut receiver) = bounded_stream(Some(2), OverflowPolicy::DropOldest);
+        sender.send_with_priority(Priority::High, 0).ok().unwrap();
+        sender.send_with_priority(Priority::Low, 1).ok().unwrap();
+        sender.send(2).ok().unwrap();
+        sender.send(3).ok().unwrap();
+
+        // The lowest priority goes first, then the oldest command.
+        assert_eq!(receiver.recv(), Some(0));
+        assert_eq!(receiver.recv(), Some(3));
+        assert!(receiver.recv().is_none());
+        assert_eq!(sender.stats(), Some(StreamStats {
+            sent: 4, dropped: 2, pending: 0, scheduled: 0, peak: 2
+        }));
+    }
+
+    #[test]
+    fn overflow_drop_oldest_without_capacity() {
+        let (mut sender, receiver) = bounded_stream(Some(0), OverflowPolicy::DropOldest);
+        assert!(sender.send(0).is_err());
+        assert_eq!(receiver.len(), 0);
+        assert_eq!(receiver.stats().dropped, 1);
+    }
+
+    #[test]
+    fn overflow_grow() {
+        let (mut sender, mut receiver) = bounded_stream(Some(1), OverflowPolicy::Grow);
+        for i in range(0, 3us) {
+            sender.send(i).ok().unwrap();
+        }
+        assert_eq!(receiver.stats(), StreamStats {
+            sent: 3, dropped: 0, pending: 3, scheduled: 0, peak: 3
+        });
+
+        while receiver.recv().is_some() {}
+        assert_eq!(receiver.stats().peak, 3);
+        assert_eq!(receiver.stats().pending, 0);
+    }
+
+    #[test]
+    fn overflow_scheduled() {
+        let (mut sender, mut receiver) = bounded_stream(Some(2), OverflowPolicy::Reject);
+        sender.send_after(1., 0).ok().unwrap();
+        sender.send(1).ok().unwrap();
+        match sender.send_every_ticks(1, 2) {
+            Err(SendError::Full(2)) => {},
+            _ => panic!("The full stream should reject the scheduled command")
+        }
+        assert_eq!(receiver.stats(), StreamStats {
+            sent: 2, dropped: 1, pending: 1, scheduled: 1, peak: 2
+        });
+
+        // Rescheduling a repeating command doesn't count as sending it.
+        assert_eq!(receiver.recv(), Some(1));
+        sender.send_every_ticks(1, 3).ok().unwrap();
+        receiver.advance_tick();
+        assert_eq!(run_due(&mut receiver), vec![3]);
+        assert_eq!(receiver.stats().sent, 3);
+        assert_eq!(receiver.stats().scheduled, 2);
+    }
+
+    #[test]
+    fn overflow_drop_oldest_scheduled() {
+        let (mut sender, receiver) = bounded_stream(Some(1), OverflowPolicy::DropOldest);
+        sender.send(0).ok().unwrap();
+        sender.send_after(1., 1).ok().unwrap();
+        assert_eq!(receiver.stats().pending, 0);
+
+        // Scheduled commands aren't dropped to make room.
+        assert!(sender.send(2).is_err());
+        assert_eq!(receiver.stats().scheduled, 1);
+    }
+
+    #[test]
+    fn stats_disconnected() {
+        let (mut sender, receiver) = stream::<usize>();
+        drop(receiver);
+
+        assert!(sender.stats().is_none());
+        match sender.send(0) {
+            Err(SendError::Disconnected(0)) => {},
+            _ => panic!("The stream should be disconnected")
+        }
+    }
+
+    #[test]
+    fn scheduled_order() {
+        let (mut sender, mut receiver) = stream();
+        sender.send_after(2., 0).ok().unwrap();
+        sender.send_after(1., 1).ok().unwrap();
+        sender.send_after(1., 2).ok().unwrap();
+
+        receiver.advance_time(0.5);
+        assert!(run_due(&mut receiver).is_empty());
+        receiver.advance_time(0.5);
+        assert_eq!(run_due(&mut receiver), vec![1, 2]);
+        receiver.advance_time(1.);
+        assert_eq!(run_due(&mut receiver), vec![0]);
+        assert!(receiver.recv().is_none());
+    }
+
+    #[test]
+    fn scheduled_at_tick() {
+        let (mut sender, mut receiver) = stream();
+        sender.send_at_tick(2, 0).ok().unwrap();
+
+        receiver.advance_tick();
+        assert!(run_due(&mut receiver).is_empty());
+        receiver.advance_tick();
+        assert_eq!(run_due(&mut receiver), vec![0]);
+
+        sender.send_at_tick(1, 1).ok().unwrap();
+        receiver.advance_tick();
+        assert_eq!(run_due(&mut receiver), vec![1]);
+    }
+
+    #[test]
+    fn scheduled_without_delay() {
+        let (mut sender, mut receiver) = stream();
+        receiver.advance_time(1.);
+
+        sender.send_after(0., 0).ok().unwrap();
+        assert!(run_due(&mut receiver).is_empty());
+        receiver.advance_time(0.);
+        assert_eq!(run_due(&mut receiver), vec![0]);
+    }
+
+    #[test]
+    fn scheduled_negative_delay() {
+        let (mut sender, mut receiver) = stream();
+        receiver.advance_time(1.);
+
+        sender.send_after(-5., 0).ok().unwrap();
+        receiver.advance_time(0.);
+        assert_eq!(run_due(&mut receiver), vec![0]);
+    }
+
+    #[test]
+    #[should_fail]
+    fn scheduled_nan_delay() {
+        let (mut sender, _receiver) = stream::<usize>();
+        sender.send_after(Float::nan(), 0).ok().unwrap();
+    }
+
+    #[test]
+    #[should_fail]
+    fn scheduled_infinite_delay() {
+        let (mut sender, _receiver) = stream::<usize>();
+        sender.send_after(Float::infinity(), 0).ok().unwrap();
+    }
+
+    #[test]
+    fn cancel() {
+        let (mut sender, mut receiver) = stream();
+        let once = sender.send_after(1., 0).ok().unwrap();
+        let repeating = sender.send_every(1., 1).ok().unwrap();
+
+        once.cancel();
+        assert!(once.is_cancelled());
+        receiver.advance_time(1.);
+        assert_eq!(run_due(&mut receiver), vec![1]);
+
+        repeating.cancel();
+        receiver.advance_time(1.);
+        assert!(run_due(&mut receiver).is_empty());
+    }
+
+    #[test]
+    fn scheduled_when_disconnected() {
+        let (mut sender, receiver) = stream::<usize>();
+        drop(receiver);
+
+        match sender.send_after(1., 0) {
+            Err(SendError::Disconnected(0)) => {},
+            _ => panic!("The stream should be disconnected")
+        }
+        assert!(sender.send_every_ticks(1, 0).is_err());
+    }
+
+    #[test]
+    fn reschedule() {
+        let (mut sender, mut receiver) = stream();
+        sender.send_every(1., 0).ok().unwrap();
+
+        receiver.advance_time(1.);
+        assert_eq!(run_due(&mut receiver), vec![0]);
+        receiver.advance_time(1.);
+        assert_eq!(run_due(&mut receiver), vec![0]);
+
+        // A late command isn't run several times to catch up.
+        receiver.advance_time(3.5);
+        assert_eq!(run_due(&mut receiver), vec![0]);
+        receiver.advance_time(0.5);
+        assert!(run_due(&mut receiver).is_empty());
+        receiver.advance_time(0.5);
+        assert_eq!(run_due(&mut receiver), vec![0]);
+    }
+
+    #[test]
+    fn reschedule_ticks() {
+        let (mut sender, mut receiver) = stream();
+        sender.send_every_ticks(2, 0).ok().unwrap();
+
+        let mut runs = 0;
+        for _ in range(0, 6us) {
+            receiver.advance_tick();
+            runs += run_due(&mut receiver).len();
+        }
+        assert_eq!(runs, 3);
+    }
+
+    #[test]
+    fn long_running_clock() {
+        let (mut sender, mut receiver) = stream();
+        receiver.advance_time(5000.);
+
+        // Such small steps would be lost by a single precision clock.
+        sender.send_after(0.0005, 0).ok().unwrap();
+        for _ in range(0, 10us) {
+            receiver.advance_time(0.0001);
+        }
+        assert_eq!(run_due(&mut receiver), vec![0]);
+    }
+
+    #[test]
+    fn sync_scheduled() {
+        let (mut sender, mut receiver) = sync_stream();
+        let handle = sender.send_every_ticks(1, 0).ok().unwrap();
+
+        receiver.advance_tick();
+        let scheduled = receiver.recv_due().unwrap();
+        assert_eq!(scheduled.command, 0);
+        receiver.reschedule(scheduled);
+
+        handle.cancel();
+        receiver.advance_tick();
+        assert!(receiver.recv_due().is_none());
+    }
+}
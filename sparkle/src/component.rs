@@ -1,10 +1,13 @@
 //! The component related features.
 
-use std::collections::VecMap;
+use std::collections::{VecMap, HashMap, HashSet, BitvSet};
 use std::any::TypeId;
 use std::raw::TraitObject;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::intrinsics;
 use std::mem;
-use entity::{Entity, MetaEntity};
+use entity::{self, Entity, MetaEntity, EntityMapper, EntityRef, RefPolicy, DebugNames};
 
 /// The trait for components.
 ///
@@ -19,6 +22,22 @@ pub fn index_of<C>() -> usize
     <C as Component>::index_of()
 }
 
+/// Returns the name of a component type, for debugging purposes.
+pub fn name_of<C>() -> &'static str
+    where C: Component
+{
+    unsafe { intrinsics::type_name::<C>() }
+}
+
+/// The trait for components holding references to other entities.
+///
+/// Such components should be inserted with `ComponentMapper::insert_referrer`,
+/// their references will then be invalidated when the referenced entities are removed.
+pub trait EntityReferrer: Component {
+    /// Calls `func` with each entity reference held by the component.
+    fn each_ref(&mut self, func: &mut FnMut(&mut EntityRef));
+}
+
 // FIXME: find a better way to do this
 struct StoreWrapper(Box<AnyStore>, TraitObject);
 
@@ -45,43 +64,138 @@ impl StoreWrapper {
 /// Basically a vector of component stores where
 /// each index corresponds to a specific component type.
 pub struct ComponentMapper {
-    stores: VecMap<StoreWrapper>
+    stores: VecMap<StoreWrapper>,
+    type_names: VecMap<&'static str>,
+    names: DebugNames,
+    refs: RefIndex
 }
 
 impl ComponentMapper {
     /// Creates a new `ComponentMapper`.
     pub fn new() -> ComponentMapper {
         ComponentMapper {
-            stores: VecMap::new()
+            stores: VecMap::new(),
+            type_names: VecMap::new(),
+            names: Rc::new(RefCell::new(VecMap::new())),
+            refs: RefIndex::new()
         }
     }
 
+    /// Uses the given entity debug names in error messages.
+    ///
+    /// This is done by `Space` with the names of its `EntityMapper`.
+    pub fn use_debug_names(&mut self, names: DebugNames) {
+        for (_, store) in self.stores.iter_mut() {
+            store.0.use_debug_names(names.clone());
+        }
+        self.names = names;
+    }
+
+    /// Returns the names of the given component types, for debugging purposes.
+    ///
+    /// Only the types for which a store is used are named.
+    pub fn component_names(&self, components: &BitvSet) -> Vec<&'static str> {
+        components.iter().filter_map(|type_index| self.type_names.get(&type_index))
+                         .map(|name| *name)
+                         .collect()
+    }
+
     /// Attaches a component to an entity and inserts it into the mapper.
     ///
     /// If necessary, a default component store is created.
+    /// The references of a replaced component holding entity references are forgotten,
+    /// see `insert_referrer`.
     pub fn insert<C>(&mut self, mentity: &mut MetaEntity, component: C)
         where C: Component
     {
         let type_index = index_of::<C>();
         mentity.components.insert(type_index);
+        if self.refs.invalidators.contains_key(&type_index) {
+            self.refs.remove((mentity.entity, type_index));
+        }
 
         self.ensure::<C>();
         self.get_store_mut::<C>().insert(mentity.entity, component);
     }
 
+    /// Attaches components to many entities at once and inserts them into the mapper.
+    ///
+    /// The store is looked up only once for the whole batch:
+    ///
+    /// ```ignore
+    /// let particles = em.create_entities(10000);
+    /// cm.insert_batch(&mut em, particles.map(|p| (p, Particle::new())));
+    /// ```
+    pub fn insert_batch<C, I>(&mut self, em: &mut EntityMapper, components: I)
+        where C: Component, I: Iterator<Item=(Entity, C)>
+    {
+        let type_index = index_of::<C>();
+        let is_referrer = self.refs.invalidators.contains_key(&type_index);
+        let mut replaced = Vec::new();
+
+        self.ensure::<C>();
+        {
+            let store = self.get_store_mut::<C>();
+            for (entity, component) in components {
+                em.get_mentity_mut(entity).components.insert(type_index);
+                if is_referrer {
+                    replaced.push(entity);
+                }
+                store.insert(entity, component);
+            }
+        }
+        for entity in replaced.into_iter() {
+            self.refs.remove((entity, type_index));
+        }
+    }
+
+    /// Attaches a component holding entity references to an entity and inserts it into the mapper.
+    ///
+    /// The references are indexed to be invalidated when the referenced entities are removed,
+    /// replacing the ones of the previous component, if any.
+    pub fn insert_referrer<C>(&mut self, mentity: &mut MetaEntity, mut component: C)
+        where C: EntityReferrer
+    {
+        let type_index = index_of::<C>();
+        let targets = targets_of(&mut component);
+        self.refs.invalidators.insert(type_index, invalidate::<C> as Invalidator);
+
+        self.insert(mentity, component);
+        self.refs.insert((mentity.entity, type_index), targets);
+    }
+
+    /// Indexes again the entity references of an entity's component.
+    ///
+    /// This must be called after changing the references of a component in place.
+    pub fn update_refs<C>(&mut self, entity: Entity)
+        where C: EntityReferrer
+    {
+        let type_index = index_of::<C>();
+        let targets = match self.try_get_mut::<C>(entity) {
+            Some(component) => targets_of(component),
+            None => Vec::new()
+        };
+
+        self.refs.invalidators.insert(type_index, invalidate::<C> as Invalidator);
+        self.refs.remove((entity, type_index));
+        self.refs.insert((entity, type_index), targets);
+    }
+
     /// Uses the given component store for a certain type of components.
     ///
     /// This should be done when setting up the mapper, before it's actually used.
     /// Panics if another store is already used for this type of components.
-    pub fn use_store<C, S>(&mut self, store: S)
+    pub fn use_store<C, S>(&mut self, mut store: S)
         where C: Component, S: ComponentStore<C>
     {
         let type_index = index_of::<C>();
 
         if !self.stores.contains_key(&type_index) {
+            store.use_debug_names(self.names.clone());
             self.stores.insert(type_index, StoreWrapper::new(store));
+            self.type_names.insert(type_index, name_of::<C>());
         } else {
-            panic!("a store is already used for this type of components");
+            panic!("a store is already used for the components {}", name_of::<C>());
         }
     }
 
@@ -94,8 +208,10 @@ impl ComponentMapper {
         let type_index = index_of::<C>();
 
         if !self.stores.contains_key(&type_index) {
-            let default = DefaultStore::<C>::new();
+            let mut default = DefaultStore::<C>::new();
+            default.use_debug_names(self.names.clone());
             self.stores.insert(type_index, StoreWrapper::new(default));
+            self.type_names.insert(type_index, name_of::<C>());
         }
     }
 
@@ -114,7 +230,11 @@ impl ComponentMapper {
     pub fn get<C>(&self, entity: Entity) -> &C
         where C: Component
     {
-        self.try_get::<C>(entity).expect("failed to get the component")
+        match self.try_get::<C>(entity) {
+            Some(component) => component,
+            None => panic!("Failed to get the component {} of entity {}",
+                           name_of::<C>(), entity::label(entity, &self.names))
+        }
     }
 
     /// Tries to return a mutable reference to an entity's component, if it exists.
@@ -132,7 +252,12 @@ impl ComponentMapper {
     pub fn get_mut<C>(&mut self, entity: Entity) -> &mut C
         where C: Component
     {
-        self.try_get_mut::<C>(entity).expect("failed to get the component")
+        let names = self.names.clone();
+        match self.try_get_mut::<C>(entity) {
+            Some(component) => component,
+            None => panic!("Failed to get the component {} of entity {}",
+                           name_of::<C>(), entity::label(entity, &names))
+        }
     }
 
     /// Tries to return a reference to a component store, if it exists.
@@ -153,7 +278,10 @@ impl ComponentMapper {
     pub fn get_store<C>(&self) -> &ComponentStore<C>
         where C: Component
     {
-        self.try_get_store::<C>().expect("failed to get the store")
+        match self.try_get_store::<C>() {
+            Some(store) => store,
+            None => panic!("Failed to get the store of the components {}", name_of::<C>())
+        }
     }
 
     /// Tries to return a mutable reference to a component store, if it exists.
@@ -174,7 +302,10 @@ impl ComponentMapper {
     pub fn get_store_mut<C>(&mut self) -> &mut ComponentStore<C>
         where C: Component
     {
-        self.try_get_store_mut::<C>().expect("failed to get the store")
+        match self.try_get_store_mut::<C>() {
+            Some(store) => store,
+            None => panic!("Failed to get the store of the components {}", name_of::<C>())
+        }
     }
 
     /// Detaches a component from an entity and removes it from the mapper.
@@ -183,6 +314,7 @@ impl ComponentMapper {
     {
         let type_index = index_of::<C>();
         mentity.components.remove(&type_index);
+        self.refs.remove((mentity.entity, type_index));
 
         self.get_store_mut::<C>().remove(mentity.entity);
     }
@@ -191,11 +323,115 @@ impl ComponentMapper {
     pub fn remove_all(&mut self, mentity: &mut MetaEntity) {
         for (type_index, store) in self.stores.iter_mut() {
             mentity.components.remove(&type_index);
+            self.refs.remove((mentity.entity, type_index));
             store.0.remove(mentity.entity);
         }
     }
 }
 
+/// A component referring to other entities, identified by its entity and its type index.
+type Referrer = (Entity, usize);
+
+/// Invalidates the references of a referrer to a removed entity.
+///
+/// Returns `true` if the referrer must be removed in cascade.
+type Invalidator = fn(&mut ComponentMapper, Entity, Entity) -> bool;
+
+/// A reverse index of the references between entities.
+struct RefIndex {
+    referrers: HashMap<Entity, HashSet<Referrer>>,
+    targets: HashMap<Referrer, Vec<Entity>>,
+    invalidators: VecMap<Invalidator>
+}
+
+impl RefIndex {
+    /// Creates an empty `RefIndex`.
+    fn new() -> RefIndex {
+        RefIndex {
+            referrers: HashMap::new(),
+            targets: HashMap::new(),
+            invalidators: VecMap::new()
+        }
+    }
+
+    /// Records that the referrer refers to the given entities.
+    fn insert(&mut self, referrer: Referrer, targets: Vec<Entity>) {
+        if targets.is_empty() {
+            return;
+        }
+
+        for &target in targets.iter() {
+            if !self.referrers.contains_key(&target) {
+                self.referrers.insert(target, HashSet::new());
+            }
+            self.referrers.get_mut(&target).unwrap().insert(referrer);
+        }
+        self.targets.insert(referrer, targets);
+    }
+
+    /// Forgets the references of the referrer.
+    fn remove(&mut self, referrer: Referrer) {
+        if let Some(targets) = self.targets.remove(&referrer) {
+            for target in targets.iter() {
+                self.referrers.get_mut(target).map(|referrers| referrers.remove(&referrer));
+            }
+        }
+    }
+
+    /// Forgets the references of the referrer to the given entity.
+    fn remove_target(&mut self, referrer: Referrer, target: Entity) {
+        let is_empty = match self.targets.get_mut(&referrer) {
+            Some(targets) => {
+                targets.retain(|&t| t != target);
+                targets.is_empty()
+            },
+            None => false
+        };
+        if is_empty {
+            self.targets.remove(&referrer);
+        }
+    }
+
+    /// Takes the referrers of an entity out of the index.
+    fn take_referrers(&mut self, target: Entity) -> Vec<Referrer> {
+        match self.referrers.remove(&target) {
+            Some(referrers) => referrers.into_iter().collect(),
+            None => Vec::new()
+        }
+    }
+}
+
+/// Returns the entities referred to by a component.
+fn targets_of<C>(component: &mut C) -> Vec<Entity>
+    where C: EntityReferrer
+{
+    let mut targets = Vec::new();
+    component.each_ref(&mut |entity_ref: &mut EntityRef| {
+        entity_ref.get().map(|target| targets.push(target));
+    });
+
+    targets
+}
+
+/// The `Invalidator` of a type of components.
+fn invalidate<C>(mapper: &mut ComponentMapper, referrer: Entity, target: Entity) -> bool
+    where C: EntityReferrer
+{
+    let mut cascade = false;
+    if let Some(component) = mapper.try_get_mut::<C>(referrer) {
+        component.each_ref(&mut |entity_ref: &mut EntityRef| {
+            if entity_ref.get() == Some(target) {
+                match entity_ref.policy() {
+                    RefPolicy::Nullify => entity_ref.nullify(),
+                    RefPolicy::Cascade => cascade = true
+                }
+            }
+        });
+    }
+
+    cascade
+}
+
 /// A store of components of the same type.
 pub trait ComponentStore<C>: 'static
     where C: Component
@@ -208,20 +444,39 @@ pub trait ComponentStore<C>: 'static
     fn try_get(&self, entity: Entity) -> Option<&C>;
     /// Tries to return a mutable reference to an entity's component.
     fn try_get_mut(&mut self, entity: Entity) -> Option<&mut C>;
+
+    /// Uses the given entity debug names in error messages.
+    ///
+    /// This is done by the `ComponentMapper` using the store.
+    fn use_debug_names(&mut self, _names: DebugNames) {}
+
+    /// Formats an entity identifier for error messages.
+    #[doc(hidden)]
+    fn label(&self, entity: Entity) -> String {
+        format!("{}", entity)
+    }
     
     /// Returns a reference to an entity's component.
     ///
     /// Panics if the entity doesn't have the requested component.
     #[inline]
     fn get(&self, entity: Entity) -> &C {
-        self.try_get(entity).expect("failed to get component")
+        match self.try_get(entity) {
+            Some(component) => component,
+            None => panic!("Failed to get the component {} of entity {}",
+                           name_of::<C>(), self.label(entity))
+        }
     }
     /// Returns a mutable reference to an entity's component.
     ///
     /// Panics if the entity doesn't have the requested component.
     #[inline]
     fn get_mut(&mut self, entity: Entity) -> &mut C {
-        self.try_get_mut(entity).expect("failed to get component")
+        if self.try_get(entity).is_none() {
+            panic!("Failed to get the component {} of entity {}",
+                   name_of::<C>(), self.label(entity));
+        }
+        self.try_get_mut(entity).unwrap()
     }
 
     #[doc(hidden)]
@@ -257,6 +512,7 @@ impl<C> ComponentStore<C>
 /// A `ComponentStore` of any component type.
 trait AnyStore: 'static {
     fn remove(&mut self, entity: Entity);
+    fn use_debug_names(&mut self, names: DebugNames);
 }
 
 #[old_impl_check]
@@ -266,20 +522,30 @@ impl<S, C> AnyStore for S
     fn remove(&mut self, entity: Entity) {
         self.remove(entity);
     }
+
+    fn use_debug_names(&mut self, names: DebugNames) {
+        self.use_debug_names(names);
+    }
 }
 
 /// The default `ComponentStore`.
 ///
 /// Basically a vector of components where
 /// each index corresponds to an `Entity`.
-pub struct DefaultStore<C>(VecMap<C>) where C: Component;
+pub struct DefaultStore<C> where C: Component {
+    components: VecMap<C>,
+    names: DebugNames
+}
 
 impl<C> DefaultStore<C>
     where C: Component
 {
     /// Creates a new `DefaultStore`.
     pub fn new() -> DefaultStore<C> {
-        DefaultStore(VecMap::new())
+        DefaultStore {
+            components: VecMap::new(),
+            names: Rc::new(RefCell::new(VecMap::new()))
+        }
     }
 }
 
@@ -288,36 +554,269 @@ impl<C> ComponentStore<C> for DefaultStore<C>
 {
     #[inline]
     fn insert(&mut self, entity: Entity, component: C) {
-        self.0.insert(entity, component);
+        self.components.insert(entity, component);
     }
 
     #[inline]
     fn remove(&mut self, entity: Entity) {
-        self.0.remove(&entity);
+        self.components.remove(&entity);
     }
 
     #[inline]
     fn try_get(&self, entity: Entity) -> Option<&C> {
-        self.0.get(&entity)
+        self.components.get(&entity)
     }
 
     #[inline]
     fn try_get_mut(&mut self, entity: Entity) -> Option<&mut C> {
-        self.0.get_mut(&entity)
+        self.components.get_mut(&entity)
+    }
+
+    fn use_debug_names(&mut self, names: DebugNames) {
+        self.names = names;
+    }
+
+    fn label(&self, entity: Entity) -> String {
+        entity::label(entity, &self.names)
     }
 }
 
 #[doc(hidden)]
 pub mod private {
     use super::ComponentMapper;
-    use entity::MetaEntity;
+    use entity::{Entity, MetaEntity};
 
     /// Forgets an entity, removing it from the `ComponentMapper`
     /// without touching the meta entity data.
     pub fn forget(mapper: &mut ComponentMapper, mentity: &MetaEntity) {
         for type_index in mentity.components.iter() {
+            mapper.refs.remove((mentity.entity, type_index));
             mapper.stores.get_mut(&type_index)
                          .map(|mut store| store.0.remove(mentity.entity));
         }
     }
+
+    /// Invalidates the references to a removed entity.
+    ///
+    /// The references are removed from the index.
+    /// Returns the referrers whose references were nulled
+    /// and the ones to remove in cascade.
+    pub fn invalidate_refs(mapper: &mut ComponentMapper, target: Entity)
+                           -> (Vec<Entity>, Vec<Entity>) {
+        let mut changed = Vec::new();
+        let mut cascaded = Vec::new();
+
+        for (referrer, type_index) in mapper.refs.take_referrers(target).into_iter() {
+            mapper.refs.remove_target((referrer, type_index), target);
+            let invalidator = match mapper.refs.invalidators.get(&type_index) {
+                Some(invalidator) => *invalidator,
+                None => continue
+            };
+
+            if invalidator(mapper, referrer, target) {
+                cascaded.push(referrer);
+            } else {
+                changed.push(referrer);
+            }
+        }
+
+        (changed, cascaded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use entity::{Entity, MetaEntity, EntityMapper, EntityObserver, EntityRef, RefPolicy};
+
+    struct Owned {
+        owner: EntityRef
+    }
+
+    impl Component for Owned {
+        fn index_of() -> usize { 0 }
+    }
+
+    impl EntityReferrer for Owned {
+        fn each_ref(&mut self, func: &mut FnMut(&mut EntityRef)) {
+            func(&mut self.owner);
+        }
+    }
+
+    /// Records the notified changes and removals.
+    struct Observer {
+        changed: Vec<Entity>,
+        removed: Vec<Entity>
+    }
+
+    impl Observer {
+        fn new() -> Observer {
+            Observer {
+                changed: Vec::new(),
+                removed: Vec::new()
+            }
+        }
+    }
+
+    impl EntityObserver for Observer {
+        fn notify_changed(&mut self, _cm: &ComponentMapper, mentity: &MetaEntity) {
+            self.changed.push(mentity.entity);
+        }
+        fn notify_slept(&mut self, _cm: &ComponentMapper, _mentity: &MetaEntity) {}
+        fn notify_woken(&mut self, _cm: &ComponentMapper, _mentity: &MetaEntity) {}
+        fn notify_removed(&mut self, _cm: &ComponentMapper, mentity: &MetaEntity) {
+            self.removed.push(mentity.entity);
+        }
+    }
+
+    /// Creates an entity owned by `owner`, and flushes the creation events.
+    fn create_owned(em: &mut EntityMapper, cm: &mut ComponentMapper,
+                    owner: Entity, policy: RefPolicy) -> Entity {
+        let entity = em.create_entity();
+        cm.insert_referrer(em.get_mentity_mut(entity), Owned {
+            owner: EntityRef::new(owner, policy)
+        });
+        em.notify_events(cm, &mut Observer::new());
+        entity
+    }
+
+    #[test]
+    fn cascade() {
+        let mut em = EntityMapper::new();
+        let mut cm = ComponentMapper::new();
+        let owner = em.create_entity();
+        let owned = create_owned(&mut em, &mut cm, owner, RefPolicy::Cascade);
+
+        let mut observer = Observer::new();
+        em.remove_entity(owner);
+        em.notify_events(&mut cm, &mut observer);
+
+        assert_eq!(observer.removed, vec![owner, owned]);
+        assert!(observer.changed.is_empty());
+        assert!(cm.try_get::<Owned>(owned).is_none());
+        assert!(em.entities().is_empty());
+    }
+
+    #[test]
+    fn nullify() {
+        let mut em = EntityMapper::new();
+        let mut cm = ComponentMapper::new();
+        let owner = em.create_entity();
+        let owned = create_owned(&mut em, &mut cm, owner, RefPolicy::Nullify);
+
+        let mut observer = Observer::new();
+        em.remove_entity(owner);
+        em.notify_events(&mut cm, &mut observer);
+
+        assert_eq!(observer.removed, vec![owner]);
+        assert_eq!(observer.changed, vec![owned]);
+        assert!(cm.get::<Owned>(owned).owner.is_null());
+        assert!(!cm.refs.targets.contains_key(&(owned, index_of::<Owned>())));
+        assert!(!cm.refs.referrers.contains_key(&owner));
+    }
+
+    #[test]
+    fn cascade_chain() {
+        let mut em = EntityMapper::new();
+        let mut cm = ComponentMapper::new();
+        let root = em.create_entity();
+        let middle = create_owned(&mut em, &mut cm, root, RefPolicy::Cascade);
+        let leaf = create_owned(&mut em, &mut cm, middle, RefPolicy::Cascade);
+
+        let mut observer = Observer::new();
+        em.remove_entity(root);
+        em.notify_events(&mut cm, &mut observer);
+
+        assert_eq!(observer.removed, vec![root, middle, leaf]);
+        assert!(em.entities().is_empty());
+    }
+
+    #[test]
+    fn nullify_stops_chain() {
+        let mut em = EntityMapper::new();
+        let mut cm = ComponentMapper::new();
+        let root = em.create_entity();
+        let middle = create_owned(&mut em, &mut cm, root, RefPolicy::Nullify);
+        let leaf = create_owned(&mut em, &mut cm, middle, RefPolicy::Cascade);
+
+        let mut observer = Observer::new();
+        em.remove_entity(root);
+        em.notify_events(&mut cm, &mut observer);
+
+        assert_eq!(observer.removed, vec![root]);
+        assert_eq!(observer.changed, vec![middle]);
+        assert_eq!(em.entities(), vec![middle, leaf]);
+        assert_eq!(cm.get::<Owned>(leaf).owner.get(), Some(middle));
+    }
+
+    #[test]
+    fn update_refs() {
+        let mut em = EntityMapper::new();
+        let mut cm = ComponentMapper::new();
+        let first = em.create_entity();
+        let second = em.create_entity();
+        let owned = create_owned(&mut em, &mut cm, first, RefPolicy::Cascade);
+
+        cm.get_mut::<Owned>(owned).owner.set(second);
+        cm.update_refs::<Owned>(owned);
+
+        let mut observer = Observer::new();
+        em.remove_entity(first);
+        em.notify_events(&mut cm, &mut observer);
+        assert_eq!(observer.removed, vec![first]);
+
+        em.remove_entity(second);
+        em.notify_events(&mut cm, &mut observer);
+        assert_eq!(observer.removed, vec![first, second, owned]);
+    }
+
+    #[test]
+    fn update_refs_of_plain_insertion() {
+        let mut em = EntityMapper::new();
+        let mut cm = ComponentMapper::new();
+        let owner = em.create_entity();
+        let owned = em.create_entity();
+        cm.insert(em.get_mentity_mut(owned), Owned {
+            owner: EntityRef::new(owner, RefPolicy::Cascade)
+        });
+        cm.update_refs::<Owned>(owned);
+
+        let mut observer = Observer::new();
+        em.remove_entity(owner);
+        em.notify_events(&mut cm, &mut observer);
+        assert_eq!(observer.removed, vec![owner, owned]);
+    }
+
+    #[test]
+    fn insert_replacing_referrer() {
+        let mut em = EntityMapper::new();
+        let mut cm = ComponentMapper::new();
+        let owner = em.create_entity();
+        let owned = create_owned(&mut em, &mut cm, owner, RefPolicy::Cascade);
+
+        cm.insert(em.get_mentity_mut(owned), Owned {
+            owner: EntityRef::new(owned, RefPolicy::Cascade)
+        });
+        assert!(!cm.refs.targets.contains_key(&(owned, index_of::<Owned>())));
+
+        let mut observer = Observer::new();
+        em.remove_entity(owner);
+        em.notify_events(&mut cm, &mut observer);
+        assert_eq!(observer.removed, vec![owner]);
+        assert_eq!(em.entities(), vec![owned]);
+    }
+
+    #[test]
+    fn store_labels() {
+        let mut em = EntityMapper::new();
+        let mut cm = ComponentMapper::new();
+        let entity = em.create_entity();
+        cm.ensure::<Owned>();
+        cm.use_debug_names(em.debug_names());
+
+        em.set_name(entity, "bullet");
+        assert_eq!(cm.get_store::<Owned>().label(entity).as_slice(), "0 (bullet)");
+        em.clear_name(entity);
+        assert_eq!(cm.get_store::<Owned>().label(entity).as_slice(), "0");
+    }
 }
@@ -1,70 +1,339 @@
 //! Identification of entities using groups.
 //!
-//! A group has a name and can contain multiple entities.   
+//! A group has a name and can contain multiple entities.
 //! An entity can belong to multiple groups.
+//!
+//! Group names are interned, groups being handled through `GroupId`s.
+//!
+//! Groups can be iterated and combined lazily, without any allocation.
+//!
+//! Group names are hierarchical, their segments being separated by `/`.
+//! A `GroupPattern` can use wildcards to match multiple groups:
+//! `*` matches exactly one segment and `**` matches any number of segments.
+//! For example, `"enemies/*"` matches `"enemies/flying"` but not `"enemies"`,
+//! whereas `"enemies/**"` matches both.
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{VecMap, HashSet, BitvSet};
+use std::collections::hash_set;
 
 use entity::{Entity, MetaEntity};
+use entity::intern::{GroupId, GroupNames};
 
 type Group = HashSet<Entity>;
 
 /// A `GroupMap` is keeping track of entity groups.
 pub struct GroupMap {
-    groups: HashMap<String, Group>
+    groups: VecMap<Group>
 }
 
 impl GroupMap {
     /// Creates an empty `GroupMap`.
     pub fn new() -> GroupMap {
         GroupMap {
-            groups: HashMap::new()
+            groups: VecMap::new()
         }
     }
 
     /// Inserts an entity into a group.
     ///
     /// Creates the group if necessary.
-    pub fn insert_in(&mut self, mentity: &mut MetaEntity, name: &str) {
-        mentity.groups.insert(name.to_string());
+    pub fn insert_in(&mut self, mentity: &mut MetaEntity, group: GroupId) {
+        mentity.groups.insert(group.0);
         let entity = mentity.entity;
 
-        self.ensure(name);
-        self.groups.get_mut(name).unwrap().insert(entity);
+        self.ensure(group);
+        self.groups.get_mut(&group.0).unwrap().insert(entity);
     }
 
     /// Ensures the group presence.
-    fn ensure(&mut self, name: &str) {
-        if !self.groups.contains_key(name) {
+    fn ensure(&mut self, group: GroupId) {
+        if !self.groups.contains_key(&group.0) {
             let empty = HashSet::new();
-            self.groups.insert(name.to_string(), empty);
+            self.groups.insert(group.0, empty);
         }
     }
 
     /// Removes an entity from a group.
-    pub fn remove_from(&mut self, mentity: &mut MetaEntity, name: &str) {
+    pub fn remove_from(&mut self, mentity: &mut MetaEntity, group: GroupId) {
         let entity = mentity.entity;
 
-        self.groups.get_mut(name).map(|group| group.remove(&entity));
-        mentity.groups.remove(name);
+        self.groups.get_mut(&group.0).map(|group| group.remove(&entity));
+        mentity.groups.remove(&group.0);
     }
 
     /// Clears an entity groups.
     pub fn clear_entity(&mut self, mentity: &mut MetaEntity) {
-        let entity = mentity.entity;
+        private::forget(self, mentity);
+        mentity.groups.clear();
+    }
 
-        for (_name, group) in self.groups.iter_mut() {
-            group.remove(&entity);
+    /// Removes a group if it is empty.
+    ///
+    /// Returns `true` if the group was removed.
+    pub fn remove_group(&mut self, group: GroupId) -> bool {
+        let is_empty = self.groups.get(&group.0).map_or(false, |group| group.is_empty());
+        if is_empty {
+            self.groups.remove(&group.0);
         }
-        mentity.groups.clear();
+        is_empty
+    }
+
+    /// Takes all entities out of a group, removing the group.
+    ///
+    /// The meta entities are not updated.
+    pub fn take(&mut self, group: GroupId) -> HashSet<Entity> {
+        self.groups.remove(&group.0).unwrap_or_else(|| HashSet::new())
     }
 
     /// Returns a group of entity as a vector.
-    pub fn get(&self, name: &str) -> Vec<Entity> {
-        match self.groups.get(name) {
-            Some(group) => group.iter().map(|entity| *entity).collect(),
-            None => Vec::new()
+    pub fn get(&self, group: GroupId) -> Vec<Entity> {
+        self.iter(Some(group)).collect()
+    }
+
+    /// Returns the entities of a group, if it exists.
+    ///
+    /// A group whose name was never interned is given as `None`.
+    fn group(&self, group: Option<GroupId>) -> Option<&Group> {
+        group.and_then(|group| self.groups.get(&group.0))
+    }
+
+    /// Returns an iterator over a group of entity.
+    pub fn iter(&self, group: Option<GroupId>) -> GroupIter {
+        GroupIter {
+            inner: self.group(group).map(|group| group.iter())
+        }
+    }
+
+    /// Returns the number of entities in a group.
+    pub fn len(&self, group: Option<GroupId>) -> usize {
+        self.group(group).map_or(0, |group| group.len())
+    }
+
+    /// Returns the existing groups as a vector.
+    pub fn ids(&self) -> Vec<GroupId> {
+        self.groups.keys().map(|id| GroupId(id)).collect()
+    }
+
+    /// Returns the existing groups matching a pattern as a vector.
+    pub fn matching(&self, pattern: &GroupPattern, names: &GroupNames) -> Vec<GroupId> {
+        pattern.select(self.ids(), names)
+    }
+
+    /// Returns the entities belonging to any group matching a pattern.
+    pub fn get_matching(&self, pattern: &GroupPattern, names: &GroupNames) -> HashSet<Entity> {
+        let mut entities = HashSet::new();
+        for id in self.matching(pattern, names).into_iter() {
+            entities.extend(self.iter(Some(id)));
+        }
+
+        entities
+    }
+
+    /// Returns a lazy iterator over the entities belonging to either group.
+    pub fn union(&self, a: Option<GroupId>, b: Option<GroupId>) -> GroupUnion {
+        GroupUnion {
+            first: self.iter(a),
+            rest: self.difference(b, a)
+        }
+    }
+
+    /// Returns a lazy iterator over the entities belonging to both groups.
+    pub fn intersection(&self, a: Option<GroupId>, b: Option<GroupId>) -> GroupIntersection {
+        GroupIntersection {
+            iter: self.iter(a),
+            other: self.group(b)
+        }
+    }
+
+    /// Returns a lazy iterator over the entities belonging to `a` but not to `b`.
+    pub fn difference(&self, a: Option<GroupId>, b: Option<GroupId>) -> GroupDifference {
+        GroupDifference {
+            iter: self.iter(a),
+            other: self.group(b)
+        }
+    }
+}
+
+/// The separator of group name segments.
+pub const GROUP_SEPARATOR: char = '/';
+
+/// A segment of a `GroupPattern`.
+#[derive(Clone, PartialEq, Show)]
+enum Segment {
+    Name(String),
+    Any,
+    AnyDepth
+}
+
+/// A pattern matching hierarchical group names.
+///
+/// The matching group identifiers are cached,
+/// the group names interned since the last refresh being checked by `refresh`.
+pub struct GroupPattern {
+    segments: Vec<Segment>,
+    ids: BitvSet,
+    checked: usize
+}
+
+impl GroupPattern {
+    /// Parses a group pattern.
+    pub fn new(pattern: &str) -> GroupPattern {
+        let segments = pattern.split(GROUP_SEPARATOR).map(|segment| {
+            match segment {
+                "*" => Segment::Any,
+                "**" => Segment::AnyDepth,
+                name => Segment::Name(name.to_string())
+            }
+        }).collect();
+
+        GroupPattern {
+            segments: segments,
+            ids: BitvSet::new(),
+            checked: 0
+        }
+    }
+
+    /// Returns `true` if the group name contains wildcards.
+    pub fn is_pattern(group: &str) -> bool {
+        group.split(GROUP_SEPARATOR).any(|segment| segment == "*" || segment == "**")
+    }
+
+    /// Returns `true` if the group name matches the pattern.
+    pub fn matches(&self, group: &str) -> bool {
+        let segments: Vec<&str> = group.split(GROUP_SEPARATOR).collect();
+        match_segments(self.segments.as_slice(), segments.as_slice())
+    }
+
+    /// Checks the group names interned since the last refresh.
+    ///
+    /// The names must be the ones of the previous refreshes, unless the pattern was reset.
+    pub fn refresh(&mut self, names: &GroupNames) {
+        if self.checked == names.len() {
+            return;
+        }
+
+        let GroupPattern { ref segments, ref mut ids, ref mut checked } = *self;
+        names.each_from(*checked, |id, name| {
+            let name: Vec<&str> = name.split(GROUP_SEPARATOR).collect();
+            if match_segments(segments.as_slice(), name.as_slice()) {
+                ids.insert(id.0);
+            }
+        });
+        *checked = names.len();
+    }
+
+    /// Forgets the cached groups, so that the pattern can be refreshed with other names.
+    pub fn reset(&mut self) {
+        self.ids.clear();
+        self.checked = 0;
+    }
+
+    /// Returns `true` if the group matched the pattern at the last refresh.
+    pub fn matches_id(&self, group: GroupId) -> bool {
+        self.ids.contains(&group.0)
+    }
+
+    /// Returns `true` if any of the groups matched the pattern at the last refresh.
+    pub fn matches_any(&self, groups: &BitvSet) -> bool {
+        !self.ids.is_disjoint(groups)
+    }
+
+    /// Returns the given groups whose names match the pattern.
+    ///
+    /// This is a one-shot match, the cache is neither used nor refreshed.
+    pub fn select(&self, ids: Vec<GroupId>, names: &GroupNames) -> Vec<GroupId> {
+        let group_names = names.names(ids.iter().map(|id| *id));
+        ids.into_iter().zip(group_names.iter())
+                       .filter(|&(_, name)| self.matches(name.as_slice()))
+                       .map(|(id, _)| id)
+                       .collect()
+    }
+}
+
+/// Matches name segments against pattern segments.
+fn match_segments(pattern: &[Segment], name: &[&str]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some(&Segment::AnyDepth) => {
+            range(0, name.len() + 1).any(|skipped| match_segments(&pattern[1..], &name[skipped..]))
+        },
+        Some(&Segment::Any) => {
+            !name.is_empty() && match_segments(&pattern[1..], &name[1..])
+        },
+        Some(&Segment::Name(ref segment)) => {
+            !name.is_empty() && name[0] == segment.as_slice() &&
+            match_segments(&pattern[1..], &name[1..])
+        }
+    }
+}
+
+/// An iterator over a group of entities.
+pub struct GroupIter<'a> {
+    inner: Option<hash_set::Iter<'a, Entity>>
+}
+
+impl<'a> Iterator for GroupIter<'a> {
+    type Item = Entity;
+
+    fn next(&mut self) -> Option<Entity> {
+        self.inner.as_mut().and_then(|iter| iter.next()).map(|entity| *entity)
+    }
+}
+
+/// A lazy iterator over the union of two groups.
+pub struct GroupUnion<'a> {
+    first: GroupIter<'a>,
+    rest: GroupDifference<'a>
+}
+
+impl<'a> Iterator for GroupUnion<'a> {
+    type Item = Entity;
+
+    fn next(&mut self) -> Option<Entity> {
+        self.first.next().or_else(|| self.rest.next())
+    }
+}
+
+/// A lazy iterator over the intersection of two groups.
+pub struct GroupIntersection<'a> {
+    iter: GroupIter<'a>,
+    other: Option<&'a Group>
+}
+
+impl<'a> Iterator for GroupIntersection<'a> {
+    type Item = Entity;
+
+    fn next(&mut self) -> Option<Entity> {
+        let other = match self.other {
+            Some(other) => other,
+            None => return None
+        };
+
+        for entity in self.iter.by_ref() {
+            if other.contains(&entity) {
+                return Some(entity);
+            }
+        }
+        None
+    }
+}
+
+/// A lazy iterator over the difference of two groups.
+pub struct GroupDifference<'a> {
+    iter: GroupIter<'a>,
+    other: Option<&'a Group>
+}
+
+impl<'a> Iterator for GroupDifference<'a> {
+    type Item = Entity;
+
+    fn next(&mut self) -> Option<Entity> {
+        for entity in self.iter.by_ref() {
+            if !self.other.map_or(false, |other| other.contains(&entity)) {
+                return Some(entity);
+            }
         }
+        None
     }
 }
 
@@ -76,116 +345,285 @@ pub mod private {
     /// Forgets an entity, removing it from the `GroupMap`
     /// without touching the meta entity data.
     pub fn forget(group_map: &mut GroupMap, mentity: &MetaEntity) {
-        for name in mentity.groups.iter() {
-            let group = group_map.groups.get_mut(name)
-                                        .expect(format!("Failed to forget {}", name).as_slice());
-
-            group.remove(&mentity.entity);
+        for id in mentity.groups.iter() {
+            match group_map.groups.get_mut(&id) {
+                Some(group) => { group.remove(&mentity.entity); },
+                None => panic!("Failed to forget group {} of entity {}", id, mentity.entity)
+            }
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::GroupMap;
+    use std::collections::BitvSet;
+
+    use super::{GroupMap, GroupPattern};
     use entity::MetaEntity;
+    use entity::intern::{GroupId, GroupNames};
 
     #[test]
     fn insert_in() {
+        let names = GroupNames::new();
         let mut group_map = GroupMap::new();
-        let group_name = "aGroup";
+        let group = names.intern("aGroup");
         let mentity1 = &mut MetaEntity::new(0);
         let mentity2 = &mut MetaEntity::new(1);
 
-        group_map.insert_in(mentity1, group_name);
-        group_map.insert_in(mentity2, group_name);
+        group_map.insert_in(mentity1, group);
+        group_map.insert_in(mentity2, group);
 
         assert_eq!(1, mentity1.groups.len());
         assert_eq!(1, mentity2.groups.len());
 
-        let group_string = group_name.to_string();
-        let expected = Some(&group_string);
+        let expected = Some(group.0);
         assert_eq!(expected, mentity1.groups.iter().next());
         assert_eq!(expected, mentity2.groups.iter().next());
 
-        let group = group_map.groups.get(group_name).unwrap();
+        let group = group_map.groups.get(&group.0).unwrap();
         assert_eq!(2, group.len());
     }
 
     #[test]
     fn ensure() {
+        let names = GroupNames::new();
         let mut group_map = GroupMap::new();
-        let group_name = "aGroup";
+        let group = names.intern("aGroup");
 
-        group_map.ensure(group_name);
+        group_map.ensure(group);
 
         assert_eq!(1, group_map.groups.len());
     }
 
     #[test]
     fn remove_from() {
+        let names = GroupNames::new();
         let mut group_map = GroupMap::new();
-        let group_name = "aGroup";
+        let group = names.intern("aGroup");
         let mentity = &mut MetaEntity::new(0);
 
-        group_map.insert_in(mentity, group_name);
-        group_map.remove_from(mentity, group_name);
+        group_map.insert_in(mentity, group);
+        group_map.remove_from(mentity, group);
 
         assert_eq!(0, mentity.groups.len());
 
-        let group = group_map.groups.get(group_name).unwrap();
+        let group = group_map.groups.get(&group.0).unwrap();
         assert_eq!(0, group.len());
     }
 
     #[test]
     fn clear_entity() {
+        let names = GroupNames::new();
         let mut group_map = GroupMap::new();
-        let group_name1 = "aGroup1";    
-        let group_name2 = "aGroup2";
+        let group1 = names.intern("aGroup1");
+        let group2 = names.intern("aGroup2");
         let mentity = &mut MetaEntity::new(0);
 
-        group_map.insert_in(mentity, group_name1);
-        group_map.insert_in(mentity, group_name2);
+        group_map.insert_in(mentity, group1);
+        group_map.insert_in(mentity, group2);
         group_map.clear_entity(mentity);
 
         assert_eq!(0, mentity.groups.len());
 
-        let group1 = group_map.groups.get(group_name1).unwrap();
+        let group1 = group_map.groups.get(&group1.0).unwrap();
         assert_eq!(0, group1.len());
 
-        let group2 = group_map.groups.get(group_name2).unwrap();
+        let group2 = group_map.groups.get(&group2.0).unwrap();
         assert_eq!(0, group2.len());
     }
 
     #[test]
     fn get() {
+        let names = GroupNames::new();
         let mut group_map = GroupMap::new();
-        let group_name = "aGroup";
-        let mentity = &mut MetaEntity::new(0);  
-    
-        group_map.insert_in(mentity, group_name); 
-    
-        let entities = group_map.get(group_name);
+        let group = names.intern("aGroup");
+        let mentity = &mut MetaEntity::new(0);
+
+        group_map.insert_in(mentity, group);
+
+        let entities = group_map.get(group);
         let expected = vec!(mentity.entity);
 
         assert_eq!(expected, entities);
     }
 
+    #[test]
+    fn iter_and_len() {
+        let names = GroupNames::new();
+        let mut group_map = GroupMap::new();
+        let group = names.intern("aGroup");
+        let empty = names.intern("anEmptyGroup");
+
+        group_map.insert_in(&mut MetaEntity::new(0), group);
+        group_map.insert_in(&mut MetaEntity::new(1), group);
+
+        let mut entities: Vec<_> = group_map.iter(Some(group)).collect();
+        entities.sort();
+        assert_eq!(entities, vec![0, 1]);
+        assert_eq!(group_map.len(Some(group)), 2);
+        assert_eq!(group_map.iter(Some(empty)).count(), 0);
+        assert_eq!(group_map.len(Some(empty)), 0);
+        assert_eq!(group_map.iter(None).count(), 0);
+        assert_eq!(group_map.len(None), 0);
+    }
+
+    #[test]
+    fn set_operations() {
+        let names = GroupNames::new();
+        let mut group_map = GroupMap::new();
+        let allies = Some(names.intern("allies"));
+        let visible = Some(names.intern("visible"));
+        let missing = Some(names.intern("aMissingGroup"));
+
+        group_map.insert_in(&mut MetaEntity::new(0), allies.unwrap());
+        {
+            let both = &mut MetaEntity::new(1);
+            group_map.insert_in(both, allies.unwrap());
+            group_map.insert_in(both, visible.unwrap());
+        }
+        group_map.insert_in(&mut MetaEntity::new(2), visible.unwrap());
+
+        let mut union: Vec<_> = group_map.union(allies, visible).collect();
+        union.sort();
+        assert_eq!(union, vec![0, 1, 2]);
+
+        let intersection: Vec<_> = group_map.intersection(allies, visible).collect();
+        assert_eq!(intersection, vec![1]);
+
+        let difference: Vec<_> = group_map.difference(allies, visible).collect();
+        assert_eq!(difference, vec![0]);
+
+        assert_eq!(group_map.intersection(allies, missing).count(), 0);
+        assert_eq!(group_map.difference(allies, missing).count(), 2);
+        assert_eq!(group_map.union(missing, visible).count(), 2);
+        assert_eq!(group_map.intersection(allies, None).count(), 0);
+        assert_eq!(group_map.difference(allies, None).count(), 2);
+        assert_eq!(group_map.union(None, visible).count(), 2);
+    }
+
+    #[test]
+    fn remove_group() {
+        let names = GroupNames::new();
+        let mut group_map = GroupMap::new();
+        let group = names.intern("aGroup");
+        let mentity = &mut MetaEntity::new(0);
+
+        group_map.insert_in(mentity, group);
+        assert!(!group_map.remove_group(group));
+
+        group_map.remove_from(mentity, group);
+        assert!(group_map.remove_group(group));
+        assert!(group_map.ids().is_empty());
+    }
+
+    #[test]
+    fn take() {
+        let names = GroupNames::new();
+        let mut group_map = GroupMap::new();
+        let group = names.intern("aGroup");
+        let mentity = &mut MetaEntity::new(0);
+
+        group_map.insert_in(mentity, group);
+        let taken = group_map.take(group);
+
+        assert!(taken.contains(&mentity.entity));
+        assert_eq!(group_map.len(Some(group)), 0);
+        assert!(group_map.ids().is_empty());
+    }
+
+    #[test]
+    fn pattern_matches() {
+        let one = GroupPattern::new("enemies/*");
+        let any = GroupPattern::new("enemies/**");
+        let inner = GroupPattern::new("*/flying");
+
+        assert!(one.matches("enemies/flying"));
+        assert!(!one.matches("enemies"));
+        assert!(!one.matches("enemies/flying/fast"));
+        assert!(any.matches("enemies"));
+        assert!(any.matches("enemies/flying/fast"));
+        assert!(!any.matches("allies/flying"));
+        assert!(inner.matches("allies/flying"));
+        assert!(!inner.matches("allies/ground"));
+
+        assert!(GroupPattern::is_pattern("enemies/**"));
+        assert!(!GroupPattern::is_pattern("enemies/flying"));
+    }
+
+    #[test]
+    fn get_matching() {
+        let names = GroupNames::new();
+        let mut group_map = GroupMap::new();
+        let flying = names.intern("pattern_test/flying");
+        let ground = names.intern("pattern_test/ground");
+        let pattern = GroupPattern::new("pattern_test/*");
+
+        {
+            let both = &mut MetaEntity::new(0);
+            group_map.insert_in(both, flying);
+            group_map.insert_in(both, ground);
+        }
+        assert_eq!(group_map.get_matching(&pattern, &names).len(), 1);
+
+        let fast = names.intern("pattern_test/fast");
+        group_map.insert_in(&mut MetaEntity::new(1), fast);
+        assert_eq!(group_map.get_matching(&pattern, &names).len(), 2);
+        assert_eq!(group_map.matching(&pattern, &names).len(), 3);
+    }
+
+    #[test]
+    fn refresh() {
+        let names = GroupNames::new();
+        let flying = names.intern("pattern_test/flying");
+        let mut pattern = GroupPattern::new("pattern_test/*");
+        assert!(!pattern.matches_id(flying));
+
+        pattern.refresh(&names);
+        assert!(pattern.matches_id(flying));
+
+        let fast = names.intern("pattern_test/fast");
+        let root = names.intern("pattern_test");
+        pattern.refresh(&names);
+        assert!(pattern.matches_id(fast));
+        assert!(!pattern.matches_id(root));
+
+        let mut groups = BitvSet::new();
+        groups.insert(root.0);
+        assert!(!pattern.matches_any(&groups));
+        groups.insert(fast.0);
+        assert!(pattern.matches_any(&groups));
+
+        pattern.reset();
+        assert!(!pattern.matches_id(flying));
+    }
+
+    #[test]
+    fn select() {
+        let names = GroupNames::new();
+        let flying = names.intern("enemies/flying");
+        let allies = names.intern("allies");
+        let pattern = GroupPattern::new("enemies/**");
+
+        assert_eq!(pattern.select(vec![allies, flying], &names), vec![flying]);
+        assert!(!pattern.matches_id(flying));
+    }
+
     #[test]
     fn forget() {
+        let names = GroupNames::new();
         let mut group_map = GroupMap::new();
-        let group_name1 = "aGroup1";    
-        let group_name2 = "aGroup2";
+        let group1 = names.intern("aGroup1");
+        let group2 = names.intern("aGroup2");
         let mentity = &mut MetaEntity::new(0);
 
-        group_map.insert_in(mentity, group_name1);
-        group_map.insert_in(mentity, group_name2);
+        group_map.insert_in(mentity, group1);
+        group_map.insert_in(mentity, group2);
         super::private::forget(&mut group_map, mentity);
 
-        let group1 = group_map.groups.get(group_name1).unwrap();
+        let group1 = group_map.groups.get(&group1.0).unwrap();
         assert_eq!(0, group1.len());
 
-        let group2 = group_map.groups.get(group_name2).unwrap();
+        let group2 = group_map.groups.get(&group2.0).unwrap();
         assert_eq!(0, group2.len());
     }
-}
\ No newline at end of file
+}
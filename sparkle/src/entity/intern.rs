@@ -0,0 +1,176 @@
+//! Interning of group and tag names.
+//!
+//! Each `EntityMapper` interns its names into its own tables,
+//! so that groups and tags can be handled through compact identifiers,
+//! just like component types.
+//!
+//! The group names are shared with the entity filters, which resolve
+//! their groups once instead of comparing names for each entity.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+/// The identifier of an interned group name.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Show)]
+pub struct GroupId(pub usize);
+
+/// The identifier of an interned tag.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Show)]
+pub struct TagId(pub usize);
+
+/// The group names interned by an `EntityMapper`.
+///
+/// The handle can be cloned, the clones sharing the same names.
+#[derive(Clone)]
+pub struct GroupNames {
+    interner: Rc<RefCell<Interner<String>>>
+}
+
+impl GroupNames {
+    /// Creates empty group names.
+    pub fn new() -> GroupNames {
+        GroupNames {
+            interner: Rc::new(RefCell::new(Interner::new()))
+        }
+    }
+
+    /// Returns the identifier of a group name, interning it if necessary.
+    pub fn intern(&self, name: &str) -> GroupId {
+        GroupId(self.interner.borrow_mut().intern(&name.to_string()))
+    }
+
+    /// Returns the identifier of a group name if it was already interned.
+    pub fn find(&self, name: &str) -> Option<GroupId> {
+        self.interner.borrow().find(&name.to_string()).map(|id| GroupId(id))
+    }
+
+    /// Returns the name of a group.
+    pub fn name(&self, id: GroupId) -> String {
+        self.interner.borrow().key(id.0).clone()
+    }
+
+    /// Returns the names of several groups.
+    pub fn names<I>(&self, ids: I) -> Vec<String>
+        where I: Iterator<Item=GroupId>
+    {
+        let interner = self.interner.borrow();
+        ids.map(|id| interner.key(id.0).clone()).collect()
+    }
+
+    /// Returns the number of interned group names.
+    ///
+    /// Group identifiers range from zero to this number.
+    pub fn len(&self) -> usize {
+        self.interner.borrow().len()
+    }
+
+    /// Calls `func` with the groups interned from the identifier `from` onwards.
+    pub fn each_from<F>(&self, from: usize, mut func: F)
+        where F: FnMut(GroupId, &str)
+    {
+        let interner = self.interner.borrow();
+        for (id, name) in interner.keys.iter().enumerate().skip(from) {
+            func(GroupId(id), name.as_slice());
+        }
+    }
+}
+
+/// Maps keys to consecutive identifiers, and back.
+pub struct Interner<K> {
+    ids: HashMap<K, usize>,
+    keys: Vec<K>
+}
+
+impl<K> Interner<K>
+    where K: Hash + Eq + Clone
+{
+    /// Creates an empty `Interner`.
+    pub fn new() -> Interner<K> {
+        Interner {
+            ids: HashMap::new(),
+            keys: Vec::new()
+        }
+    }
+
+    /// Returns the identifier of a key, interning it if necessary.
+    pub fn intern(&mut self, key: &K) -> usize {
+        if let Some(&id) = self.ids.get(key) {
+            return id;
+        }
+
+        let id = self.keys.len();
+        self.keys.push(key.clone());
+        self.ids.insert(key.clone(), id);
+        id
+    }
+
+    /// Returns the identifier of a key if it was already interned.
+    pub fn find(&self, key: &K) -> Option<usize> {
+        self.ids.get(key).map(|id| *id)
+    }
+
+    /// Returns the key of an identifier.
+    pub fn key(&self, id: usize) -> &K {
+        &self.keys[id]
+    }
+
+    /// Returns the number of interned keys.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GroupId, GroupNames, Interner};
+
+    #[test]
+    fn interner() {
+        let mut interner = Interner::new();
+        let a = "a".to_string();
+        let b = "b".to_string();
+
+        assert_eq!(interner.intern(&a), 0);
+        assert_eq!(interner.intern(&b), 1);
+        assert_eq!(interner.intern(&a), 0);
+        assert_eq!(interner.find(&b), Some(1));
+        assert!(interner.find(&"c".to_string()).is_none());
+        assert_eq!(interner.key(1), &b);
+    }
+
+    #[test]
+    fn group_names() {
+        let names = GroupNames::new();
+        let id = names.intern("group");
+
+        assert_eq!(names.intern("group"), id);
+        assert_eq!(names.clone().find("group"), Some(id));
+        assert_eq!(names.name(id).as_slice(), "group");
+        assert!(names.find("missing_group").is_none());
+        assert_eq!(names.len(), 1);
+    }
+
+    #[test]
+    fn names() {
+        let names = GroupNames::new();
+        let first = names.intern("first");
+        let second = names.intern("second");
+
+        assert_eq!(names.names(vec![second, first].into_iter()),
+                   vec!["second".to_string(), "first".to_string()]);
+        assert!(names.names(Vec::new().into_iter()).is_empty());
+    }
+
+    #[test]
+    fn each_from() {
+        let names = GroupNames::new();
+        names.intern("first");
+        names.intern("second");
+
+        let mut seen = Vec::new();
+        names.each_from(1, |id, name| seen.push((id, name.to_string())));
+        assert_eq!(seen, vec![(GroupId(1), "second".to_string())]);
+    }
+}
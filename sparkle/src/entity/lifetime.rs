@@ -0,0 +1,229 @@
+//! Timed removal of entities.
+//!
+//! An entity can be given a lifetime, either in seconds or in fixed update ticks,
+//! after which it is removed.
+
+use std::collections::HashMap;
+use std::num::Float;
+
+use entity::Entity;
+use system::InterSystemCommand;
+
+/// The remaining lifetime of an entity.
+#[derive(Copy, PartialEq, Show)]
+enum Remaining {
+    Seconds(f32),
+    Ticks(usize)
+}
+
+impl Remaining {
+    /// Returns `true` if the lifetime expired.
+    fn is_expired(&self) -> bool {
+        match *self {
+            Remaining::Seconds(seconds) => seconds <= 0.,
+            Remaining::Ticks(ticks) => ticks == 0
+        }
+    }
+}
+
+/// The lifetime of an entity and the command to run on expiry.
+struct Lifetime {
+    remaining: Remaining,
+    on_expiry: Option<InterSystemCommand>
+}
+
+/// An expired entity and the command to run before its removal.
+pub type Expired = (Entity, Option<InterSystemCommand>);
+
+/// A `LifetimeMap` is keeping track of entity lifetimes.
+pub struct LifetimeMap {
+    lifetimes: HashMap<Entity, Lifetime>
+}
+
+impl LifetimeMap {
+    /// Creates an empty `LifetimeMap`.
+    pub fn new() -> LifetimeMap {
+        LifetimeMap {
+            lifetimes: HashMap::new()
+        }
+    }
+
+    /// Sets an entity lifetime in seconds.
+    ///
+    /// Overrides any previous lifetime of the entity.
+    /// Panics if the lifetime is NaN.
+    pub fn insert_seconds(&mut self, entity: Entity, seconds: f32,
+                          on_expiry: Option<InterSystemCommand>) {
+        if seconds.is_nan() {
+            panic!("The lifetime of entity {} can't be NaN", entity);
+        }
+
+        self.lifetimes.insert(entity, Lifetime {
+            remaining: Remaining::Seconds(seconds),
+            on_expiry: on_expiry
+        });
+    }
+
+    /// Sets an entity lifetime in fixed update ticks.
+    ///
+    /// Overrides any previous lifetime of the entity.
+    pub fn insert_ticks(&mut self, entity: Entity, ticks: usize,
+                        on_expiry: Option<InterSystemCommand>) {
+        self.lifetimes.insert(entity, Lifetime {
+            remaining: Remaining::Ticks(ticks),
+            on_expiry: on_expiry
+        });
+    }
+
+    /// Removes an entity lifetime.
+    ///
+    /// Returns `true` if the entity had a lifetime.
+    pub fn remove(&mut self, entity: Entity) -> bool {
+        self.lifetimes.remove(&entity).is_some()
+    }
+
+    /// Returns `true` if the entity has a lifetime.
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.lifetimes.contains_key(&entity)
+    }
+
+    /// Removes the lifetime of an entity if it expired,
+    /// returning the command to run before its removal.
+    ///
+    /// Returns `None` if the entity has no expired lifetime,
+    /// for instance if it was removed or overridden since it expired.
+    pub fn take_expired(&mut self, entity: Entity) -> Option<Expired> {
+        let is_expired = self.lifetimes.get(&entity)
+                                       .map_or(false, |lifetime| lifetime.remaining.is_expired());
+        if !is_expired {
+            return None;
+        }
+
+        let lifetime = self.lifetimes.remove(&entity).unwrap();
+        Some((entity, lifetime.on_expiry))
+    }
+
+    /// Advances the lifetimes in seconds by `dt`.
+    ///
+    /// Returns the expired entities, sorted by identifier.
+    /// Their lifetimes are kept until taken with `take_expired`.
+    pub fn advance_seconds(&mut self, dt: f32) -> Vec<Entity> {
+        self.advance(|remaining| {
+            match *remaining {
+                Remaining::Seconds(ref mut seconds) => {
+                    *seconds -= dt;
+                    *seconds <= 0.
+                },
+                Remaining::Ticks(_) => false
+            }
+        })
+    }
+
+    /// Advances the lifetimes in ticks by one tick.
+    ///
+    /// Returns the expired entities, sorted by identifier.
+    /// Their lifetimes are kept until taken with `take_expired`.
+    pub fn advance_ticks(&mut self) -> Vec<Entity> {
+        self.advance(|remaining| {
+            match *remaining {
+                Remaining::Ticks(ref mut ticks) => {
+                    if *ticks > 0 {
+                        *ticks -= 1;
+                    }
+                    *ticks == 0
+                },
+                Remaining::Seconds(_) => false
+            }
+        })
+    }
+
+    /// Advances each lifetime with `func`, which returns `true` on expiry.
+    fn advance<F>(&mut self, mut func: F) -> Vec<Entity>
+        where F: FnMut(&mut Remaining) -> bool
+    {
+        let mut expired = Vec::new();
+        for (entity, lifetime) in self.lifetimes.iter_mut() {
+            if func(&mut lifetime.remaining) {
+                expired.push(*entity);
+            }
+        }
+        expired.sort();
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::Float;
+
+    use entity::Entity;
+    use super::LifetimeMap;
+
+    /// Advances the lifetimes in seconds and takes the expired ones.
+    fn expire_seconds(lifetimes: &mut LifetimeMap, dt: f32) -> Vec<Entity> {
+        let expired = lifetimes.advance_seconds(dt);
+        expired.into_iter().filter(|&entity| lifetimes.take_expired(entity).is_some()).collect()
+    }
+
+    #[test]
+    fn advance_seconds() {
+        let mut lifetimes = LifetimeMap::new();
+        lifetimes.insert_seconds(0, 1., None);
+        lifetimes.insert_seconds(1, 0.5, None);
+        lifetimes.insert_ticks(2, 1, None);
+
+        assert!(expire_seconds(&mut lifetimes, 0.25).is_empty());
+        assert_eq!(expire_seconds(&mut lifetimes, 0.25), vec![1]);
+        assert_eq!(expire_seconds(&mut lifetimes, 0.5), vec![0]);
+        assert!(lifetimes.remove(2));
+    }
+
+    #[test]
+    fn advance_ticks() {
+        let mut lifetimes = LifetimeMap::new();
+        lifetimes.insert_ticks(0, 2, None);
+        lifetimes.insert_ticks(1, 0, None);
+        lifetimes.insert_seconds(2, 0., None);
+
+        assert_eq!(lifetimes.advance_ticks(), vec![1]);
+        assert!(lifetimes.take_expired(1).is_some());
+        assert_eq!(lifetimes.advance_ticks(), vec![0]);
+        assert!(lifetimes.take_expired(0).is_some());
+        assert!(lifetimes.remove(2));
+    }
+
+    #[test]
+    fn take_expired() {
+        let mut lifetimes = LifetimeMap::new();
+        lifetimes.insert_seconds(0, 1., None);
+        lifetimes.insert_seconds(1, 1., None);
+        lifetimes.insert_seconds(2, 1., None);
+        assert!(lifetimes.take_expired(0).is_none());
+
+        assert_eq!(lifetimes.advance_seconds(1.), vec![0, 1, 2]);
+        lifetimes.remove(1);
+        lifetimes.insert_seconds(2, 1., None);
+        assert!(lifetimes.take_expired(0).is_some());
+        assert!(lifetimes.take_expired(1).is_none());
+        assert!(lifetimes.take_expired(2).is_none());
+        assert!(!lifetimes.contains(0));
+        assert!(lifetimes.contains(2));
+    }
+
+    #[test]
+    fn remove() {
+        let mut lifetimes = LifetimeMap::new();
+        lifetimes.insert_seconds(0, 1., None);
+
+        assert!(lifetimes.remove(0));
+        assert!(!lifetimes.remove(0));
+        assert!(lifetimes.advance_seconds(2.).is_empty());
+    }
+
+    #[test]
+    #[should_fail]
+    fn insert_nan() {
+        let mut lifetimes = LifetimeMap::new();
+        lifetimes.insert_seconds(0, Float::nan(), None);
+    }
+}
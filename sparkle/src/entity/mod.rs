@@ -10,6 +10,57 @@
 //! em.remove_entity(entity);
 //! ````
 //!
+//! Many entities can also be created or removed at once,
+//! which is more efficient than doing it one by one:
+//!
+//! ````ignore
+//! let particles: Vec<Entity> = em.create_entities(10000).collect();
+//! // ...
+//! em.remove_entities(particles.into_iter());
+//! ````
+//!
+//! ## Reservation of entities
+//!
+//! Entity identifiers can be reserved from outside the `Space`,
+//! for instance by a loading thread, and materialized later from a command:
+//!
+//! ````ignore
+//! let reserver = em.reserver();
+//! // On another thread:
+//! let reservation = reserver.reserve();
+//! let entity = reservation.entity();
+//! // In a command, once back to the space:
+//! em.create_reserved(reservation);
+//! ````
+//!
+//! A reservation dropped without being materialized is reclaimed.
+//!
+//! ## Timed removal of entities
+//!
+//! Entities such as projectiles or particles can be removed after a certain time,
+//! either in seconds, driven by `Space::update`, or in ticks, driven by `Space::fixed_update`:
+//!
+//! ````ignore
+//! em.despawn_after(bullet, 2.5);
+//! em.despawn_after_ticks(spark, 30);
+//! // ...
+//! em.cancel_despawn(bullet);
+//! ````
+//!
+//! A command can also be given to be run on expiry, just before the removal.
+//!
+//! ## Debug names
+//!
+//! Entities can be given a name for debugging purposes:
+//!
+//! ````ignore
+//! em.set_name(julian, "julian");
+//! ````
+//!
+//! Unlike tags, debug names don't have to be unique.
+//! They are used in the error messages related to entities,
+//! and are still displayed when a removed entity is used, until its identifier is reused.
+//!
 //! ## Identification of entities using groups
 //!
 //! Groups are useful to identify a category of entities.
@@ -23,10 +74,31 @@
 //! let allies = em.get_group("allies");
 //! // Though you'll probably prefer using system filters than that function.
 //! ````
+//!
+//! Group names are hierarchical, their segments being separated by `/`.
+//! Lookups can use wildcards, `*` matching one segment and `**` any number of segments:
+//!
+//! ````ignore
+//! em.set_group(dragon, "enemies/flying");
+//! em.set_group(orc, "enemies/ground");
+//! let enemies = em.get_group("enemies/**");
+//! ````
+//!
+//! Groups can also be iterated and combined without allocating:
+//!
+//! ````ignore
+//! for entity in em.iter_group("allies") {
+//!     // ...
+//! }
+//! let visible_allies = em.group_intersection("allies", "visible");
+//! ````
 //! 
 //! A group is referred to by a name and can contain multiple entities.
 //! Likewise, an entity can belong to multiple groups.
 //!
+//! Group names are interned into `GroupId`s, which are used by meta entities and filters.
+//! The names are interned by each `EntityMapper`, whose `GroupNames` are given to the systems.
+//!
 //! ## Identification of entities using tags
 //!
 //! Tags are useful to identify a specific entity.
@@ -40,29 +112,127 @@
 //! ```
 //!
 //! A tag is referred to by a name and can only tag one entity at a time.
-//! Furthermore, an entity can only have one tag at a time.
+//! However, an entity can have multiple tags:
+//!
+//! ```ignore
+//! em.set_tag(julian, "player1");
+//! em.set_tag(julian, "camera_target");
+//! let tags = em.tags_of(julian);
+//! ```
+//!
+//! Tags can also be declared in a namespace, so that different subsystems
+//! can use the same tag names without clobbering each other:
+//!
+//! ```ignore
+//! em.set_tag_in(julian, "ai", "target");
+//! em.set_tag_in(bold_dwarf, "camera", "target");
+//! let ai_target = em.get_tag_in("ai", "target");
+//! ```
+//!
+//! Tagging an entity with a tag already used by another one fails by default,
+//! but a `TagPolicy` can be given to steal or swap the tag instead:
+//!
+//! ```ignore
+//! if let Err(conflict) = em.set_tag(julian, "hero") {
+//!     em.set_tag_with(julian, "hero", TagPolicy::Steal).unwrap();
+//! }
+//! ```
+//!
+//! ## Relations between entities
+//!
+//! Relations are typed edges between entities.
+//! For example, you could tell who owns what:
+//!
+//! ```ignore
+//! struct Owns;
+//! impl Relation for Owns {}
+//!
+//! em.relate::<Owns>(bold_dwarf, axe);
+//! // ...
+//! // And somewhere else:
+//! let belongings = em.related::<Owns>(bold_dwarf);
+//! let owners = em.inverse::<Owns>(axe);
+//! ```
+//!
+//! The relations of an entity are cleared when it is removed.
+//!
+//! ## References between entities
+//!
+//! Components referring to other entities should hold `EntityRef`s
+//! and be inserted with `ComponentMapper::insert_referrer`:
+//!
+//! ```ignore
+//! let owner = EntityRef::new(bob, RefPolicy::Cascade);
+//! cm.insert_referrer(em.get_mentity_mut(hat), Item { owner: owner });
+//! // ...
+//! // Removing bob will also remove the hat.
+//! em.remove_entity(bob);
+//! ```
+//!
+//! When a referenced entity is removed, each reference is either nulled,
+//! the referrer being notified as changed, or the referrer is removed as well.
 
-use std::collections::{VecMap, RingBuf, HashSet, BitvSet};
+use std::any::TypeId;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::{VecMap, RingBuf, HashMap, HashSet, BitvSet};
 use std::collections::ring_buf;
+use std::vec;
 
 use component::ComponentMapper;
+use command::Command;
+use system::InterSystemCommand;
 
 use self::group::GroupMap;
+pub use self::group::{GroupIter, GroupUnion, GroupIntersection, GroupDifference,
+    GroupPattern, GROUP_SEPARATOR};
 use self::tag::TagMap;
+use self::relation::RelationMap;
+use self::lifetime::LifetimeMap;
+use self::reserve::IdAllocator;
+
+pub use self::reference::{EntityRef, RefPolicy};
+pub use self::relation::Relation;
+pub use self::tag::{Tag, TagPolicy, TagConflict, DEFAULT_NAMESPACE};
+pub use self::intern::{GroupId, TagId, GroupNames};
+use self::intern::Interner;
+pub use self::reserve::{EntityReserver, Reservation};
 
+mod intern;
 mod group;
 mod tag;
+mod reference;
+mod relation;
+mod lifetime;
+mod reserve;
 
 /// A plain entity identifier.
 pub type Entity = usize;
 
+/// The debug names of entities, shared with the `ComponentMapper` for error messages.
+///
+/// The name of a removed entity is kept until its identifier is reused.
+pub type DebugNames = Rc<RefCell<VecMap<String>>>;
+
+/// Formats an entity identifier along with its debug name, if any.
+#[doc(hidden)]
+pub fn label(entity: Entity, names: &DebugNames) -> String {
+    match names.borrow().get(&entity) {
+        Some(name) => format!("{} ({})", entity, name),
+        None => format!("{}", entity)
+    }
+}
+
 /// An entity and its features.
 #[derive(PartialEq, Eq, Clone)]
 pub struct MetaEntity {
     pub entity: Entity,
     pub is_awake: bool,
-    pub tag: Option<String>,
-    pub groups: HashSet<String>,
+    pub tags: HashSet<TagId>,
+    pub groups: BitvSet,
+    pub relations: HashSet<TypeId>,
     pub components: BitvSet
 }
 
@@ -72,8 +242,9 @@ impl MetaEntity {
         MetaEntity {
             entity: entity,
             is_awake: true,
-            tag: None,
-            groups: HashSet::new(),
+            tags: HashSet::new(),
+            groups: BitvSet::new(),
+            relations: HashSet::new(),
             components: BitvSet::new()
         }
     }
@@ -82,8 +253,9 @@ impl MetaEntity {
     fn reset(mut self) -> MetaEntity {
         self.is_awake = true;
         self.components.clear();
-        self.tag = None;
+        self.tags.clear();
         self.groups.clear();
+        self.relations.clear();
 
         self
     }
@@ -93,7 +265,11 @@ impl MetaEntity {
 pub struct EntityMapper {
     mentities: MetaEntityMap,
     groups: GroupMap,
-    tags: TagMap
+    tags: TagMap,
+    relations: RelationMap,
+    lifetimes: LifetimeMap,
+    group_names: GroupNames,
+    tag_names: Interner<Tag>
 }
 
 impl EntityMapper {
@@ -102,7 +278,11 @@ impl EntityMapper {
         EntityMapper {
             mentities: MetaEntityMap::new(),
             groups: GroupMap::new(),
-            tags: TagMap::new()
+            tags: TagMap::new(),
+            relations: RelationMap::new(),
+            lifetimes: LifetimeMap::new(),
+            group_names: GroupNames::new(),
+            tag_names: Interner::new()
         }
     }
 
@@ -111,21 +291,183 @@ impl EntityMapper {
         self.mentities.create()
     }
 
+    /// Creates `count` new entities at once.
+    ///
+    /// The identifiers are not necessarily contiguous, since removed ones are recycled.
+    pub fn create_entities(&mut self, count: usize) -> vec::IntoIter<Entity> {
+        self.mentities.create_batch(count).into_iter()
+    }
+
+    /// Returns the existing entities, sorted by identifier.
+    pub fn entities(&self) -> Vec<Entity> {
+        self.mentities.mentities.keys().collect()
+    }
+
+    /// Returns a handle to reserve entity identifiers from outside the `EntityMapper`.
+    ///
+    /// The handle can be cloned and sent to other threads.
+    pub fn reserver(&self) -> EntityReserver {
+        EntityReserver::new(self.mentities.pool.ids.clone())
+    }
+
+    /// Creates the entity of a reservation.
+    ///
+    /// Panics if the reservation comes from the reserver of another `EntityMapper`.
+    pub fn create_reserved(&mut self, reservation: Reservation) -> Entity {
+        if !reservation.is_from(&self.mentities.pool.ids) {
+            panic!("The reservation of entity {} comes from another entity mapper",
+                   reservation.entity());
+        }
+        if self.mentities.contains(reservation.entity()) {
+            panic!("The reserved entity {} already exists", self.label(reservation.entity()));
+        }
+        self.mentities.create_reserved(reservation.materialize())
+    }
+
     /// Removes an entity.
     ///
     /// The removal event is recorded and will be notified before any system update.
     pub fn remove_entity(&mut self, entity: Entity) {
-        {
+        let emptied = {
             let mentity = self.mentities.get(entity);
             group::private::forget(&mut self.groups, mentity);
             tag::private::forget(&mut self.tags, mentity);
+            relation::private::forget(&mut self.relations, mentity)
+        };
+        for (source, kind) in emptied.into_iter() {
+            self.mentities.get_mut(source).relations.remove(&kind);
         }
+        self.lifetimes.remove(entity);
         self.mentities.remove(entity);
     }
 
+    /// Removes several entities at once.
+    ///
+    /// The groups, tags and relations are forgotten in a single pass,
+    /// and the removal events are recorded as a batch.
+    /// They will be notified before any system update.
+    pub fn remove_entities<I>(&mut self, entities: I)
+        where I: Iterator<Item=Entity>
+    {
+        let entities: Vec<Entity> = entities.collect();
+        let mut emptied = Vec::new();
+        {
+            let EntityMapper {
+                ref mentities, ref mut groups, ref mut tags, ref mut relations, ..
+            } = *self;
+            for &entity in entities.iter() {
+                let mentity = mentities.get(entity);
+                group::private::forget(groups, mentity);
+                tag::private::forget(tags, mentity);
+                emptied.extend(relation::private::forget(relations, mentity).into_iter());
+            }
+        }
+        for (source, kind) in emptied.into_iter() {
+            self.mentities.get_mut(source).relations.remove(&kind);
+        }
+        for &entity in entities.iter() {
+            self.lifetimes.remove(entity);
+        }
+        self.mentities.remove_batch(entities.as_slice());
+    }
+
+    /// Removes an entity after the given time in seconds.
+    ///
+    /// Overrides any previous timed removal of the entity.
+    /// Panics if the entity doesn't exist or if the time is NaN.
+    pub fn despawn_after(&mut self, entity: Entity, seconds: f32) {
+        self.check_despawnable(entity);
+        self.lifetimes.insert_seconds(entity, seconds, None);
+    }
+
+    /// Removes an entity after the given time in seconds,
+    /// running `on_expiry` just before the removal.
+    ///
+    /// Overrides any previous timed removal of the entity, even from `on_expiry`,
+    /// in which case the entity isn't removed.
+    /// Panics if the entity doesn't exist or if the time is NaN.
+    pub fn despawn_after_with(&mut self, entity: Entity, seconds: f32,
+                              on_expiry: InterSystemCommand) {
+        self.check_despawnable(entity);
+        self.lifetimes.insert_seconds(entity, seconds, Some(on_expiry));
+    }
+
+    /// Removes an entity after the given number of fixed update ticks.
+    ///
+    /// Overrides any previous timed removal of the entity.
+    /// Panics if the entity doesn't exist.
+    pub fn despawn_after_ticks(&mut self, entity: Entity, ticks: usize) {
+        self.check_despawnable(entity);
+        self.lifetimes.insert_ticks(entity, ticks, None);
+    }
+
+    /// Removes an entity after the given number of fixed update ticks,
+    /// running `on_expiry` just before the removal.
+    ///
+    /// Overrides any previous timed removal of the entity, even from `on_expiry`,
+    /// in which case the entity isn't removed.
+    /// Panics if the entity doesn't exist.
+    pub fn despawn_after_ticks_with(&mut self, entity: Entity, ticks: usize,
+                                    on_expiry: InterSystemCommand) {
+        self.check_despawnable(entity);
+        self.lifetimes.insert_ticks(entity, ticks, Some(on_expiry));
+    }
+
+    /// Cancels the timed removal of an entity.
+    ///
+    /// The removal of an entity which expired in the same update can still be cancelled
+    /// by the expiry command of another entity.
+    /// Returns `true` if a timed removal was cancelled.
+    pub fn cancel_despawn(&mut self, entity: Entity) -> bool {
+        self.lifetimes.remove(entity)
+    }
+
+    /// Panics if an entity to despawn doesn't exist.
+    fn check_despawnable(&self, entity: Entity) {
+        if !self.mentities.contains(entity) {
+            panic!("Can't despawn the entity {}, which doesn't exist", self.label(entity));
+        }
+    }
+
+    /// Advances the lifetimes in seconds, removing the expired entities.
+    #[doc(hidden)]
+    pub fn update_lifetimes(&mut self, cm: &mut ComponentMapper, dt: f32) {
+        let expired = self.lifetimes.advance_seconds(dt);
+        self.expire(cm, expired);
+    }
+
+    /// Advances the lifetimes in ticks, removing the expired entities.
+    #[doc(hidden)]
+    pub fn fixed_update_lifetimes(&mut self, cm: &mut ComponentMapper) {
+        let expired = self.lifetimes.advance_ticks();
+        self.expire(cm, expired);
+    }
+
+    /// Runs the expiry commands and removes the expired entities.
+    ///
+    /// The lifetimes are checked again before and after each expiry command,
+    /// which can cancel the removal of the next expired entities or give a new lifetime
+    /// to its own entity.
+    fn expire(&mut self, cm: &mut ComponentMapper, expired: Vec<Entity>) {
+        for entity in expired.into_iter() {
+            let on_expiry = match self.lifetimes.take_expired(entity) {
+                Some((_, on_expiry)) => on_expiry,
+                None => continue
+            };
+            if let Some(mut command) = on_expiry {
+                command.run((&mut *self, &mut *cm));
+            }
+            if self.mentities.contains(entity) && !self.lifetimes.contains(entity) {
+                self.remove_entity(entity);
+            }
+        }
+    }
+
     /// Enables an entity
     ///
     /// The entity will be updated by systems again.
+    /// If it was asleep, systems will be notified that it was woken up,
+    /// unless it is put back to sleep before they are.
     pub fn wake_up(&mut self, entity: Entity) {
         self.mentities.set_awake(entity, true);
     }
@@ -133,10 +475,48 @@ impl EntityMapper {
     /// Disables an entity
     ///
     /// The entity won't be updated by systems anymore.
+    /// If it was awake, systems will be notified that it was put to sleep,
+    /// unless it is woken up again before they are.
     pub fn put_to_sleep(&mut self, entity: Entity) {
         self.mentities.set_awake(entity, false);
     }
 
+    /// Sets the debug name of an entity.
+    pub fn set_name(&mut self, entity: Entity, name: &str) {
+        self.mentities.set_name(entity, Some(name.to_string()));
+    }
+
+    /// Clears the debug name of an entity.
+    pub fn clear_name(&mut self, entity: Entity) {
+        self.mentities.set_name(entity, None);
+    }
+
+    /// Returns the debug name of an entity, if any.
+    pub fn name_of(&self, entity: Entity) -> Option<String> {
+        // Panics if the entity doesn't exist.
+        self.mentities.get(entity);
+        self.mentities.names.borrow().get(&entity).map(|name| name.clone())
+    }
+
+    /// Formats an entity identifier along with its debug name, if any.
+    pub fn label(&self, entity: Entity) -> String {
+        label(entity, &self.mentities.names)
+    }
+
+    /// Returns the debug names of entities.
+    ///
+    /// They are shared with the `ComponentMapper` to be used in error messages.
+    pub fn debug_names(&self) -> DebugNames {
+        self.mentities.names.clone()
+    }
+
+    /// Returns the interned group names.
+    ///
+    /// They are given to the systems, so that their filters can resolve groups.
+    pub fn group_names(&self) -> GroupNames {
+        self.group_names.clone()
+    }
+
     /// Returns a reference to the meta entity.
     pub fn get_mentity(&self, entity: Entity) -> &MetaEntity {
         self.mentities.get(entity)
@@ -148,13 +528,27 @@ impl EntityMapper {
     }
 
     /// Inserts an entity into a group.
+    ///
+    /// Panics if the group name contains wildcards.
     pub fn set_group(&mut self, entity: Entity, group: &str) {
-        self.groups.insert_in(self.mentities.get_mut(entity), group);
+        forbid_pattern(group);
+        self.groups.insert_in(self.mentities.get_mut(entity), self.group_names.intern(group));
     }
 
     /// Removes an entity from a group.
+    ///
+    /// If the group name contains wildcards, removes the entity from all matching groups.
     pub fn unset_group(&mut self, entity: Entity, group: &str) {
-        self.groups.remove_from(self.mentities.get_mut(entity), group);
+        let ids: Vec<GroupId> = if GroupPattern::is_pattern(group) {
+            let held = self.mentities.get(entity).groups.iter().map(|id| GroupId(id)).collect();
+            GroupPattern::new(group).select(held, &self.group_names)
+        } else {
+            self.group_names.find(group).into_iter().collect()
+        };
+
+        for id in ids.into_iter() {
+            self.groups.remove_from(self.mentities.get_mut(entity), id);
+        }
     }
     
     /// Clears an entity groups.
@@ -163,51 +557,354 @@ impl EntityMapper {
     }
 
     /// Returns an entity group as a vector.
+    ///
+    /// If the group name contains wildcards, returns the entities
+    /// of all matching groups, without duplicates.
     pub fn get_group(&self, group: &str) -> Vec<Entity> {
-        self.groups.get(group)
+        if GroupPattern::is_pattern(group) {
+            self.groups.get_matching(&GroupPattern::new(group), &self.group_names)
+                       .into_iter().collect()
+        } else {
+            self.iter_group(group).collect()
+        }
     }
 
-    /// Sets an entity tag.
+    /// Returns the names of the existing groups matching a pattern as a vector.
+    pub fn matching_groups(&self, pattern: &str) -> Vec<String> {
+        let ids = self.groups.matching(&GroupPattern::new(pattern), &self.group_names);
+        self.group_names.names(ids.into_iter())
+    }
+
+    /// Returns an iterator over an entity group.
+    ///
+    /// An unknown group is empty.
+    /// Panics if the group name contains wildcards, use `get_group` instead.
+    pub fn iter_group(&self, group: &str) -> GroupIter {
+        forbid_pattern(group);
+        self.groups.iter(self.group_names.find(group))
+    }
+
+    /// Returns the number of entities in a group.
     ///
-    /// If the entity was already tagged, the previous tag will be overriden and returned.
-    /// Panics if the tag was already used.
-    pub fn set_tag(&mut self, entity: Entity, tag: &str) -> Option<String> {
+    /// If the group name contains wildcards, returns the number
+    /// of entities belonging to any matching group.
+    pub fn group_len(&self, group: &str) -> usize {
+        if GroupPattern::is_pattern(group) {
+            self.groups.get_matching(&GroupPattern::new(group), &self.group_names).len()
+        } else {
+            self.groups.len(self.group_names.find(group))
+        }
+    }
+
+    /// Returns the names of the existing groups as a vector.
+    pub fn list_groups(&self) -> Vec<String> {
+        self.group_names.names(self.groups.ids().into_iter())
+    }
+
+    /// Returns the names of the groups of an entity as a vector.
+    pub fn groups_of(&self, entity: Entity) -> Vec<String> {
+        self.group_names.names(self.mentities.get(entity).groups.iter().map(|id| GroupId(id)))
+    }
+
+    /// Returns a lazy iterator over the entities belonging to either group.
+    ///
+    /// Panics if a group name contains wildcards.
+    pub fn group_union(&self, a: &str, b: &str) -> GroupUnion {
+        forbid_pattern(a);
+        forbid_pattern(b);
+        self.groups.union(self.group_names.find(a), self.group_names.find(b))
+    }
+
+    /// Returns a lazy iterator over the entities belonging to both groups.
+    ///
+    /// Panics if a group name contains wildcards.
+    pub fn group_intersection(&self, a: &str, b: &str) -> GroupIntersection {
+        forbid_pattern(a);
+        forbid_pattern(b);
+        self.groups.intersection(self.group_names.find(a), self.group_names.find(b))
+    }
+
+    /// Returns a lazy iterator over the entities belonging to `a` but not to `b`.
+    ///
+    /// Panics if a group name contains wildcards.
+    pub fn group_difference(&self, a: &str, b: &str) -> GroupDifference {
+        forbid_pattern(a);
+        forbid_pattern(b);
+        self.groups.difference(self.group_names.find(a), self.group_names.find(b))
+    }
+
+    /// Puts all entities of a group to sleep.
+    ///
+    /// Wildcards are supported, like for `get_group`.
+    pub fn put_group_to_sleep(&mut self, group: &str) {
+        self.set_group_awake(group, false);
+    }
+
+    /// Wakes up all entities of a group.
+    ///
+    /// Wildcards are supported, like for `get_group`.
+    pub fn wake_up_group(&mut self, group: &str) {
+        self.set_group_awake(group, true);
+    }
+
+    /// Enables or disables all entities of a group.
+    ///
+    /// Only the entities whose state actually changes are recorded as changed.
+    fn set_group_awake(&mut self, group: &str, awake: bool) {
+        for entity in self.get_group(group).into_iter() {
+            self.mentities.set_awake(entity, awake);
+        }
+    }
+
+    /// Removes all entities of a group.
+    ///
+    /// Wildcards are supported, like for `get_group`.
+    /// The removal events are recorded and will be notified before any system update.
+    pub fn remove_group_entities(&mut self, group: &str) {
+        for entity in self.get_group(group).into_iter() {
+            self.remove_entity(entity);
+        }
+    }
+
+    /// Renames a group, moving all of its entities to the new group.
+    ///
+    /// If the new group already exists, both groups are merged.
+    /// Panics if a group name contains wildcards.
+    pub fn rename_group(&mut self, from: &str, to: &str) {
+        forbid_pattern(from);
+        forbid_pattern(to);
+        let from = match self.group_names.find(from) {
+            Some(from) => from,
+            None => return
+        };
+        let to = self.group_names.intern(to);
+        if from == to {
+            return;
+        }
+
+        let EntityMapper { ref mut mentities, ref mut groups, .. } = *self;
+        for entity in groups.take(from).into_iter() {
+            let mentity = mentities.get_mut(entity);
+            mentity.groups.remove(&from.0);
+            groups.insert_in(mentity, to);
+        }
+    }
+
+    /// Deletes a group if it is empty.
+    ///
+    /// Returns `true` if the group was deleted.
+    /// Panics if the group name contains wildcards.
+    pub fn delete_group(&mut self, group: &str) -> bool {
+        forbid_pattern(group);
+        match self.group_names.find(group) {
+            Some(id) => self.groups.remove_group(id),
+            None => false
+        }
+    }
+
+    /// Adds a tag to an entity, in the default namespace.
+    ///
+    /// Fails without changing anything if the tag was already used by another entity.
+    pub fn set_tag(&mut self, entity: Entity, tag: &str) -> Result<(), TagConflict> {
+        self.set_tag_in_with(entity, DEFAULT_NAMESPACE, tag, TagPolicy::Fail)
+    }
+
+    /// Adds a tag to an entity, in the given namespace.
+    ///
+    /// Fails without changing anything if the tag was already used by another entity.
+    pub fn set_tag_in(&mut self, entity: Entity, namespace: &str, tag: &str)
+        -> Result<(), TagConflict>
+    {
+        self.set_tag_in_with(entity, namespace, tag, TagPolicy::Fail)
+    }
+
+    /// Adds a tag to an entity, in the default namespace.
+    ///
+    /// If the tag was already used by another entity, the policy is applied.
+    pub fn set_tag_with(&mut self, entity: Entity, tag: &str, policy: TagPolicy)
+        -> Result<(), TagConflict>
+    {
+        self.set_tag_in_with(entity, DEFAULT_NAMESPACE, tag, policy)
+    }
+
+    /// Adds a tag to an entity, in the given namespace.
+    ///
+    /// If the tag was already used by another entity, the policy is applied.
+    /// Only the `Fail` policy can return an error, in which case nothing is changed.
+    pub fn set_tag_in_with(&mut self, entity: Entity, namespace: &str, tag: &str,
+                           policy: TagPolicy) -> Result<(), TagConflict>
+    {
+        let qualified = Tag::in_namespace(namespace, tag);
+        let tag = TagId(self.tag_names.intern(&qualified));
+        // Ensures the entity exists before changing anything.
+        self.mentities.get(entity);
+
+        match self.tags.get(tag) {
+            Some(holder) if holder != entity => match policy {
+                TagPolicy::Fail => return Err(TagConflict { tag: qualified, holder: holder }),
+                TagPolicy::Steal => {
+                    self.tags.remove(self.mentities.get_mut(holder), tag);
+                },
+                TagPolicy::Swap => {
+                    self.tags.remove(self.mentities.get_mut(holder), tag);
+
+                    let swapped: Vec<TagId> = {
+                        let EntityMapper { ref mentities, ref tag_names, .. } = *self;
+                        mentities.get(entity).tags.iter()
+                            .filter(|t| tag_names.key(t.0).namespace.as_slice() == namespace)
+                            .map(|t| *t)
+                            .collect()
+                    };
+                    for t in swapped.into_iter() {
+                        self.tags.remove(self.mentities.get_mut(entity), t);
+                        if self.tags.insert(self.mentities.get_mut(holder), t).is_err() {
+                            panic!("Failed to swap a freed tag to entity {}",
+                                   self.label(holder));
+                        }
+                    }
+                }
+            },
+            _ => {}
+        }
+
         self.tags.insert(self.mentities.get_mut(entity), tag)
+                 .map_err(|holder| TagConflict { tag: qualified, holder: holder })
+    }
+
+    /// Removes a tag of an entity, in the default namespace.
+    pub fn remove_tag(&mut self, entity: Entity, tag: &str) {
+        self.remove_tag_in(entity, DEFAULT_NAMESPACE, tag);
+    }
+
+    /// Removes a tag of an entity, in the given namespace.
+    pub fn remove_tag_in(&mut self, entity: Entity, namespace: &str, tag: &str) {
+        if let Some(id) = self.tag_names.find(&Tag::in_namespace(namespace, tag)) {
+            self.tags.remove(self.mentities.get_mut(entity), TagId(id));
+        }
+    }
+
+    /// Clears an entity tags, in every namespace.
+    pub fn clear_entity_tags(&mut self, entity: Entity) {
+        self.tags.clear_entity(self.mentities.get_mut(entity))
     }
 
-    /// Unsets an entity tag.
-    pub fn unset_tag(&mut self, entity: Entity) {
-        self.tags.remove(self.mentities.get_mut(entity))
+    /// Returns the tags of an entity as a vector, in every namespace.
+    pub fn tags_of(&self, entity: Entity) -> Vec<Tag> {
+        self.mentities.get(entity).tags.iter()
+                                      .map(|tag| self.tag_names.key(tag.0).clone())
+                                      .collect()
     }
 
-    /// Returns the entity tagged by `tag` if it exists.
+    /// Returns the entity tagged by `tag` in the default namespace if it exists.
     ///
     /// This method returns `None` if the tag doesn't exist.
     pub fn try_get_tag(&self, tag: &str) -> Option<Entity> {
-        self.tags.get(tag)
+        self.try_get_tag_in(DEFAULT_NAMESPACE, tag)
     }
 
-    /// Returns the entity tagged by `tag`.
+    /// Returns the entity tagged by `tag` in the default namespace.
     ///
     /// This method panics if the tag doesn't exist.
     pub fn get_tag(&self, tag: &str) -> Entity {
-        self.tags.get(tag).expect(format!("Failed to find an entity with tag {}", tag).as_slice())
+        self.get_tag_in(DEFAULT_NAMESPACE, tag)
+    }
+
+    /// Returns the entity tagged by `tag` in the given namespace if it exists.
+    ///
+    /// This method returns `None` if the tag doesn't exist.
+    pub fn try_get_tag_in(&self, namespace: &str, tag: &str) -> Option<Entity> {
+        self.tag_names.find(&Tag::in_namespace(namespace, tag))
+                      .and_then(|id| self.tags.get(TagId(id)))
+    }
+
+    /// Returns the entity tagged by `tag` in the given namespace.
+    ///
+    /// This method panics if the tag doesn't exist.
+    pub fn get_tag_in(&self, namespace: &str, tag: &str) -> Entity {
+        self.try_get_tag_in(namespace, tag)
+            .expect(format!("Failed to find an entity with tag {} in namespace '{}'",
+                            tag, namespace).as_slice())
+    }
+
+    /// Relates an entity to another one.
+    ///
+    /// Panics if either entity doesn't exist.
+    pub fn relate<R>(&mut self, from: Entity, to: Entity)
+        where R: Relation
+    {
+        if !self.mentities.contains(to) {
+            panic!("Can't relate {} to the entity {}, which doesn't exist",
+                   self.label(from), self.label(to));
+        }
+        self.relations.relate::<R>(self.mentities.get_mut(from), to);
+    }
+
+    /// Removes the relation between an entity and another one.
+    pub fn unrelate<R>(&mut self, from: Entity, to: Entity)
+        where R: Relation
+    {
+        self.relations.unrelate::<R>(self.mentities.get_mut(from), to);
+    }
+
+    /// Returns the entities an entity is related to as a vector.
+    pub fn related<R>(&self, from: Entity) -> Vec<Entity>
+        where R: Relation
+    {
+        self.relations.related::<R>(from)
+    }
+
+    /// Returns the entities related to an entity as a vector.
+    pub fn inverse<R>(&self, to: Entity) -> Vec<Entity>
+        where R: Relation
+    {
+        self.relations.inverse::<R>(to)
     }
 
     /// Notify all entity events that occurred to an observer.
+    ///
+    /// The references to removed entities are invalidated,
+    /// and the resulting events are notified as well.
     #[doc(hidden)]
     pub fn notify_events<O>(&mut self, cm: &mut ComponentMapper, obs: &mut O) where O: EntityObserver {
-        let EntityMapper { ref mut mentities, .. } = *self;
-
-        mentities.drain_events_with(|(kind, mentity)| {
-            match kind {
-                EventKind::Changed => obs.notify_changed(cm, mentity),
-                EventKind::Removed => {
-                    obs.notify_removed(cm, mentity);
-                    ::component::private::forget(cm, mentity);
+        while !self.mentities.events.is_empty() {
+            let mut changed = Vec::new();
+            let mut cascaded = Vec::new();
+
+            self.mentities.drain_events_with(|(kind, mentity)| {
+                match kind {
+                    EventKind::Changed => obs.notify_changed(cm, mentity),
+                    EventKind::Slept => obs.notify_slept(cm, mentity),
+                    EventKind::Woken => obs.notify_woken(cm, mentity),
+                    EventKind::Removed => {
+                        obs.notify_removed(cm, mentity);
+                        ::component::private::forget(cm, mentity);
+
+                        let (referrers, dependents) =
+                            ::component::private::invalidate_refs(cm, mentity.entity);
+                        changed.extend(referrers.into_iter());
+                        cascaded.extend(dependents.into_iter());
+                    }
+                }
+            });
+
+            for entity in changed.into_iter() {
+                if self.mentities.contains(entity) {
+                    self.mentities.get_mut(entity);
                 }
             }
-        });
+            for entity in cascaded.into_iter() {
+                if self.mentities.contains(entity) {
+                    self.remove_entity(entity);
+                }
+            }
+        }
+    }
+}
+
+/// Panics if the group name contains wildcards.
+fn forbid_pattern(group: &str) {
+    if GroupPattern::is_pattern(group) {
+        panic!("Wildcards are not supported here, in the group name {}", group);
     }
 }
 
@@ -218,15 +915,19 @@ type Event = (EventKind, Entity);
 #[derive(Copy, PartialEq, Show)]
 enum EventKind {
     Changed,
+    Slept,
+    Woken,
     Removed
 }
 
 /// A queue recording entity-related events.
 ///
-/// Change events are protected against duplicates.
+/// Change events are protected against duplicates,
+/// and the awake transitions of an entity are collapsed to their net change.
 struct EventQueue {
     changed_set: HashSet<Entity>,
     removed_set: HashSet<Entity>,
+    awake_map: HashMap<Entity, bool>,
     events: RingBuf<Event>
 }
 
@@ -236,6 +937,7 @@ impl EventQueue {
         EventQueue {
             changed_set: HashSet::new(),
             removed_set: HashSet::new(),
+            awake_map: HashMap::new(),
             events: RingBuf::new()
         }
     }
@@ -249,6 +951,56 @@ impl EventQueue {
         }
     }
 
+    /// Records the change of several entities, ignoring duplicates.
+    fn changed_batch(&mut self, entities: &[Entity]) {
+        self.changed_set.reserve(entities.len());
+        self.events.reserve(entities.len());
+        for &entity in entities.iter() {
+            self.changed(entity);
+        }
+    }
+
+    /// Records that an entity was put to sleep.
+    fn slept(&mut self, entity: Entity) {
+        self.awake_changed(entity, false);
+    }
+
+    /// Records that an entity was woken up.
+    fn woken(&mut self, entity: Entity) {
+        self.awake_changed(entity, true);
+    }
+
+    /// Records an awake transition, ignoring duplicates.
+    ///
+    /// If the entity gets back to its awake state at the last drain,
+    /// the pending transition is dropped.
+    fn awake_changed(&mut self, entity: Entity, awake: bool) {
+        if self.removed_set.contains(&entity) {
+            return;
+        }
+
+        let (kind, reverse) = if awake {
+            (EventKind::Woken, EventKind::Slept)
+        } else {
+            (EventKind::Slept, EventKind::Woken)
+        };
+
+        match self.awake_map.get(&entity).map(|&initial| initial) {
+            None => {
+                self.awake_map.insert(entity, !awake);
+                self.events.push_back((kind, entity));
+            },
+            Some(initial) if initial == awake => {
+                self.awake_map.remove(&entity);
+                let pending = self.events.iter().position(|event| *event == (reverse, entity));
+                if let Some(i) = pending {
+                    self.events.remove(i);
+                }
+            },
+            Some(_) => {}
+        }
+    }
+
     /// Records the removal of an entity. 
     fn removed(&mut self, entity: Entity) {
         if self.removed_set.insert(entity) {
@@ -256,10 +1008,30 @@ impl EventQueue {
         }
     }
 
+    /// Records that several entities were removed.
+    fn removed_batch(&mut self, entities: &[Entity]) {
+        self.removed_set.reserve(entities.len());
+        self.events.reserve(entities.len());
+        for &entity in entities.iter() {
+            self.removed(entity);
+        }
+    }
+
+    /// Reserves capacity for at least `additional` more events.
+    fn reserve(&mut self, additional: usize) {
+        self.events.reserve(additional);
+    }
+
+    /// Returns `true` if no event was recorded.
+    fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
     /// Drains all recorded events.
     fn drain(&mut self) -> EventDrain {
         self.changed_set.clear();
         self.removed_set.clear();
+        self.awake_map.clear();
         self.events.drain()
     }
 }
@@ -271,21 +1043,29 @@ type EventDrain<'a> = ring_buf::Drain<'a, Event>;
 pub trait EntityObserver {
     /// Notifies the observer that an entity was changed.
     fn notify_changed(&mut self, cm: &ComponentMapper, mentity: &MetaEntity);
+    /// Notifies the observer that an entity was put to sleep.
+    fn notify_slept(&mut self, cm: &ComponentMapper, mentity: &MetaEntity);
+    /// Notifies the observer that an entity was woken up.
+    fn notify_woken(&mut self, cm: &ComponentMapper, mentity: &MetaEntity);
     /// Notifies the observer that an entity was removed.
     fn notify_removed(&mut self, cm: &ComponentMapper, mentity: &MetaEntity);
 }
 
 macro_rules! get_mentity {
-    ($mentities:expr, $entity:expr) => (
-        $mentities.get(&$entity)
-                  .expect(format!("There is no meta information for {}", $entity).as_slice())
+    ($mentities:expr, $names:expr, $entity:expr) => (
+        match $mentities.get(&$entity) {
+            Some(mentity) => mentity,
+            None => panic!("There is no meta information for {}", label($entity, $names))
+        }
     )
 }
 
 macro_rules! get_mentity_mut {
-    ($mentities:expr, $entity:expr) => (
-        $mentities.get_mut(&$entity)
-                  .expect(format!("There is no meta information for {}", $entity).as_slice())
+    ($mentities:expr, $names:expr, $entity:expr) => (
+        match $mentities.get_mut(&$entity) {
+            Some(mentity) => mentity,
+            None => panic!("There is no meta information for {}", label($entity, $names))
+        }
     )
 }
 
@@ -293,6 +1073,7 @@ macro_rules! get_mentity_mut {
 struct MetaEntityMap {
     pool: Pool,
     mentities: VecMap<MetaEntity>,
+    names: DebugNames,
     events: EventQueue
 }
 
@@ -302,6 +1083,7 @@ impl MetaEntityMap {
         MetaEntityMap {
             pool: Pool::new(),
             mentities: VecMap::new(),
+            names: Rc::new(RefCell::new(VecMap::new())),
             events: EventQueue::new()
         }
     }
@@ -311,6 +1093,33 @@ impl MetaEntityMap {
         let meta_entity = self.pool.get();
         let entity = meta_entity.entity;
         self.mentities.insert(entity, meta_entity);
+        self.names.borrow_mut().remove(&entity);
+
+        self.events.changed(entity);
+        entity
+    }
+
+    /// Creates `count` new entities at once.
+    fn create_batch(&mut self, count: usize) -> Vec<Entity> {
+        let batch = self.pool.get_batch(count);
+        let entities: Vec<Entity> = batch.iter().map(|mentity| mentity.entity).collect();
+
+        {
+            let mut names = self.names.borrow_mut();
+            for mentity in batch.into_iter() {
+                names.remove(&mentity.entity);
+                self.mentities.insert(mentity.entity, mentity);
+            }
+        }
+
+        self.events.changed_batch(entities.as_slice());
+        entities
+    }
+
+    /// Creates the entity of a reserved identifier.
+    fn create_reserved(&mut self, entity: Entity) -> Entity {
+        self.mentities.insert(entity, MetaEntity::new(entity));
+        self.names.borrow_mut().remove(&entity);
 
         self.events.changed(entity);
         entity
@@ -324,20 +1133,55 @@ impl MetaEntityMap {
         self.events.removed(entity);
     }
 
+    /// Removes several entities at once.
+    ///
+    /// Like for a single entity, the effective removal is delayed until the events are treated.
+    fn remove_batch(&mut self, entities: &[Entity]) {
+        self.events.removed_batch(entities);
+    }
+
     /// Enable or disable an entity.
+    ///
+    /// The change and the transition are recorded only if the state actually changes.
     fn set_awake(&mut self, entity: Entity, awake: bool) {
+        if self.get(entity).is_awake == awake {
+            return;
+        }
+
         self.get_mut(entity).is_awake = awake;
+        if awake {
+            self.events.woken(entity);
+        } else {
+            self.events.slept(entity);
+        }
+    }
+
+    /// Sets or clears the debug name of an entity.
+    ///
+    /// This isn't a change of the entity, no event is recorded.
+    fn set_name(&mut self, entity: Entity, name: Option<String>) {
+        // Panics if the entity doesn't exist.
+        self.get(entity);
+        match name {
+            Some(name) => { self.names.borrow_mut().insert(entity, name); },
+            None => { self.names.borrow_mut().remove(&entity); }
+        }
+    }
+
+    /// Returns `true` if the entity exists.
+    fn contains(&self, entity: Entity) -> bool {
+        self.mentities.contains_key(&entity)
     }
 
     /// Returns a reference to a meta entity.
     fn get(&self, entity: Entity) -> &MetaEntity {
-        get_mentity!(self.mentities, entity)
+        get_mentity!(self.mentities, &self.names, entity)
     }
 
     /// Returns a mutable reference to a meta entity.
     fn get_mut(&mut self, entity: Entity) -> &mut MetaEntity {
         self.events.changed(entity);
-        get_mentity_mut!(self.mentities, entity)
+        get_mentity_mut!(self.mentities, &self.names, entity)
     }
 
     /// Drains the entity-related events, applying `func` for each event.
@@ -346,9 +1190,9 @@ impl MetaEntityMap {
     fn drain_events_with<'a, F>(&'a mut self, mut func: F)
         where F: for<'b> FnMut((EventKind, &'b MetaEntity))
     {
-        let MetaEntityMap {ref mut events, ref mut mentities, ref mut pool} = *self;
+        let MetaEntityMap {ref mut events, ref mut mentities, ref mut pool, ref names} = *self;
         for (kind, entity) in events.drain() {
-            func((kind, get_mentity!(mentities, entity)));
+            func((kind, get_mentity!(mentities, names, entity)));
             if let EventKind::Removed = kind {
                 pool.put(mentities.remove(&entity).unwrap());
             }
@@ -359,9 +1203,10 @@ impl MetaEntityMap {
 /// A pool from where we can draw bare entities.
 ///
 /// A drawn entity can be put back in to be recycled.
+/// New identifiers are shared with the entity reservers.
 struct Pool {
     available: Vec<MetaEntity>,
-    next_id: usize
+    ids: Arc<IdAllocator>
 }
 
 impl Pool {
@@ -369,18 +1214,42 @@ impl Pool {
     fn new() -> Pool {
         Pool {
             available: Vec::new(),
-            next_id: 0
+            ids: Arc::new(IdAllocator::new())
         }
     }
 
     /// Retrieves a bare entity from the pool.
+    ///
+    /// The identifiers of dropped reservations are reclaimed first.
     fn get(&mut self) -> MetaEntity {
-        self.available.pop().unwrap_or_else(|| {
-            let id = self.next_id;
-            self.next_id += 1;
+        self.reclaim();
 
-            MetaEntity::new(id)
-        }).reset()
+        let Pool { ref mut available, ref ids } = *self;
+        available.pop().unwrap_or_else(|| MetaEntity::new(ids.next())).reset()
+    }
+
+    /// Retrieves `count` bare entities from the pool.
+    ///
+    /// Recycled entities are used first, the remaining identifiers being allocated at once.
+    fn get_batch(&mut self, count: usize) -> Vec<MetaEntity> {
+        self.reclaim();
+
+        let recycled = cmp::min(count, self.available.len());
+        let mut batch = Vec::with_capacity(count);
+        for _ in range(0, recycled) {
+            batch.push(self.available.pop().unwrap().reset());
+        }
+        for id in self.ids.next_range(count - recycled) {
+            batch.push(MetaEntity::new(id));
+        }
+        batch
+    }
+
+    /// Reclaims the identifiers of dropped reservations.
+    fn reclaim(&mut self) {
+        for entity in self.ids.take_released().into_iter() {
+            self.available.push(MetaEntity::new(entity));
+        }
     }
 
     /// Puts an entity back in the pool.
@@ -393,6 +1262,12 @@ impl Pool {
 mod tests {
     use super::*;
     use super::{EventQueue, EventKind, MetaEntityMap, Pool};       
+    use std::rc::Rc;
+    use std::cell::Cell;
+    use std::num::Float;
+
+    use component::ComponentMapper;
+    use command::FromFn;
         
     #[test]
     fn event_queue_changed() {
@@ -440,6 +1315,56 @@ mod tests {
         assert!(queue.events.pop_back().is_none());
     }
     
+    #[test]
+    fn event_queue_slept_woken() {
+        let mut queue = EventQueue::new();
+
+        queue.slept(0);
+        queue.woken(0);
+        assert!(queue.is_empty());
+
+        queue.slept(0);
+        queue.woken(0);
+        queue.slept(0);
+        assert_eq!(queue.events.pop_front(), Some((EventKind::Slept, 0)));
+        assert!(queue.events.pop_front().is_none());
+    }
+
+    #[test]
+    fn event_queue_slept_dup() {
+        let mut queue = EventQueue::new();
+
+        queue.slept(0);
+        queue.slept(0);
+        queue.woken(1);
+        assert_eq!(queue.events.pop_front(), Some((EventKind::Slept, 0)));
+        assert_eq!(queue.events.pop_front(), Some((EventKind::Woken, 1)));
+        assert!(queue.events.pop_front().is_none());
+    }
+
+    #[test]
+    fn event_queue_awake_across_drains() {
+        let mut queue = EventQueue::new();
+
+        queue.slept(0);
+        queue.drain();
+        queue.woken(0);
+        assert_eq!(queue.events.pop_front(), Some((EventKind::Woken, 0)));
+        assert!(queue.awake_map.contains_key(&0));
+        queue.drain();
+        assert!(queue.awake_map.is_empty());
+    }
+
+    #[test]
+    fn event_queue_slept_when_removed() {
+        let mut queue = EventQueue::new();
+
+        queue.removed(0);
+        queue.slept(0);
+        assert_eq!(queue.events.pop_back(), Some((EventKind::Removed, 0)));
+        assert!(queue.events.pop_back().is_none());
+    }
+
     #[test]
     fn event_queue_drain() {
         let mut queue = EventQueue::new();
@@ -471,10 +1396,12 @@ mod tests {
         let entity = mentity_map.create();
         
         let meta_entity = mentity_map.mentities.get(&entity).unwrap();
-        assert_eq!(meta_entity.entity, mentity_map.pool.next_id-1);
+        assert_eq!(meta_entity.entity, mentity_map.pool.ids.count()-1);
         assert!(meta_entity.is_awake);
-        assert!(meta_entity.tag.is_none());
+        assert!(mentity_map.names.borrow().get(&entity).is_none());
+        assert!(meta_entity.tags.is_empty());
         assert!(meta_entity.groups.is_empty());
+        assert!(meta_entity.relations.is_empty());
         assert!(meta_entity.components.is_empty());
         
         assert_eq!(mentity_map.events.events.pop_back(),
@@ -508,6 +1435,28 @@ mod tests {
         assert!(mentity_map.mentities.get(&entity).unwrap().is_awake);
     }
 
+    #[test]
+    fn mentity_map_set_awake_events() {
+        let mut mentity_map = MetaEntityMap::new();
+        let entity = mentity_map.create();
+        mentity_map.events.drain();
+
+        mentity_map.set_awake(entity, true);
+        assert!(mentity_map.events.is_empty());
+
+        mentity_map.set_awake(entity, false);
+        mentity_map.set_awake(entity, true);
+        let drained: Vec<(EventKind, Entity)> = mentity_map.events.drain().collect();
+        assert_eq!(drained, [(EventKind::Changed, entity)]);
+
+        mentity_map.set_awake(entity, false);
+        let drained: Vec<(EventKind, Entity)> = mentity_map.events.drain().collect();
+        assert_eq!(drained, [
+            (EventKind::Changed, entity),
+            (EventKind::Slept, entity)
+        ]);
+    }
+
     #[test]
     fn mentity_map_get() {
         let mut mentity_map = MetaEntityMap::new();
@@ -569,6 +1518,178 @@ mod tests {
 
 
     
+    #[test]
+    fn set_tag_fail() {
+        let mut em = EntityMapper::new();
+        let entity = em.create_entity();
+        let other = em.create_entity();
+
+        em.set_tag(entity, "tag").unwrap();
+        let conflict = TagConflict { tag: Tag::new("tag"), holder: entity };
+        assert_eq!(em.set_tag(other, "tag"), Err(conflict));
+        assert_eq!(em.get_tag("tag"), entity);
+        assert!(em.tags_of(other).is_empty());
+    }
+
+    #[test]
+    fn set_tag_steal() {
+        let mut em = EntityMapper::new();
+        let entity = em.create_entity();
+        let other = em.create_entity();
+
+        em.set_tag(entity, "tag").unwrap();
+        em.set_tag_with(other, "tag", TagPolicy::Steal).unwrap();
+        assert_eq!(em.get_tag("tag"), other);
+        assert!(em.tags_of(entity).is_empty());
+    }
+
+    #[test]
+    fn set_tag_swap() {
+        let mut em = EntityMapper::new();
+        let entity = em.create_entity();
+        let other = em.create_entity();
+
+        em.set_tag_in(entity, "ns", "first").unwrap();
+        em.set_tag_in(other, "ns", "second").unwrap();
+        em.set_tag(other, "untouched").unwrap();
+        em.set_tag_in_with(other, "ns", "first", TagPolicy::Swap).unwrap();
+        assert_eq!(em.get_tag_in("ns", "first"), other);
+        assert_eq!(em.get_tag_in("ns", "second"), entity);
+        assert_eq!(em.get_tag("untouched"), other);
+    }
+
+    #[test]
+    fn put_group_to_sleep() {
+        let mut em = EntityMapper::new();
+        let asleep = em.create_entity();
+        let awake = em.create_entity();
+        em.set_group(asleep, "enemies");
+
+        em.put_group_to_sleep("enemies");
+        assert!(!em.get_mentity(asleep).is_awake);
+        assert!(em.get_mentity(awake).is_awake);
+
+        em.wake_up_group("enemies");
+        assert!(em.get_mentity(asleep).is_awake);
+    }
+
+    #[test]
+    fn remove_group_entities() {
+        let mut em = EntityMapper::new();
+        let removed = em.create_entity();
+        let kept = em.create_entity();
+        em.set_group(removed, "projectiles");
+        em.mentities.events.drain();
+
+        em.remove_group_entities("projectiles");
+        let drained: Vec<(EventKind, Entity)> = em.mentities.events.drain().collect();
+        assert_eq!(drained, [(EventKind::Removed, removed)]);
+        assert!(em.get_group("projectiles").is_empty());
+        assert!(em.get_mentity(kept).is_awake);
+    }
+
+    #[test]
+    fn rename_group() {
+        let mut em = EntityMapper::new();
+        let entity = em.create_entity();
+        let other = em.create_entity();
+        em.set_group(entity, "old_name");
+        em.set_group(other, "new_name");
+
+        em.rename_group("old_name", "new_name");
+        let mut renamed = em.get_group("new_name");
+        renamed.sort();
+        assert_eq!(renamed, vec![entity, other]);
+        assert!(em.get_group("old_name").is_empty());
+        let old_name = em.group_names().find("old_name").unwrap();
+        assert!(!em.get_mentity(entity).groups.contains(&old_name.0));
+    }
+
+    #[test]
+    fn group_patterns() {
+        let mut em = EntityMapper::new();
+        let flying = em.create_entity();
+        let both = em.create_entity();
+        em.set_group(flying, "pattern_lookup/flying");
+        em.set_group(both, "pattern_lookup/flying");
+        em.set_group(both, "pattern_lookup/ground");
+
+        assert_eq!(em.group_len("pattern_lookup/*"), 2);
+        em.unset_group(both, "pattern_lookup/*");
+        assert!(em.get_mentity(both).groups.is_empty());
+        assert_eq!(em.get_group("pattern_lookup/**"), vec![flying]);
+    }
+
+    #[test]
+    #[should_fail]
+    fn set_group_pattern() {
+        let mut em = EntityMapper::new();
+        let entity = em.create_entity();
+        em.set_group(entity, "enemies/*");
+    }
+
+    #[test]
+    #[should_fail]
+    fn iter_group_pattern() {
+        let em = EntityMapper::new();
+        em.iter_group("enemies/**");
+    }
+
+    #[test]
+    fn unknown_group_lookups() {
+        let mut em = EntityMapper::new();
+        let entity = em.create_entity();
+        em.set_group(entity, "lookup_test_known");
+
+        assert_eq!(em.iter_group("lookup_test_unknown").count(), 0);
+        assert_eq!(em.group_len("lookup_test_unknown"), 0);
+        assert_eq!(em.group_union("lookup_test_unknown", "lookup_test_known").count(), 1);
+        assert_eq!(em.group_intersection("lookup_test_known", "lookup_test_unknown").count(), 0);
+        assert_eq!(em.group_difference("lookup_test_known", "lookup_test_unknown").count(), 1);
+        em.rename_group("lookup_test_unknown", "lookup_test_known");
+        assert!(em.group_names().find("lookup_test_unknown").is_none());
+    }
+
+    #[test]
+    fn debug_names() {
+        let mut em = EntityMapper::new();
+        let entity = em.create_entity();
+        let names = em.debug_names();
+
+        em.set_name(entity, "bullet");
+        assert_eq!(em.name_of(entity), Some("bullet".to_string()));
+        assert_eq!(em.label(entity).as_slice(), "0 (bullet)");
+        assert_eq!(label(entity, &names).as_slice(), "0 (bullet)");
+
+        em.clear_name(entity);
+        assert!(em.name_of(entity).is_none());
+        assert_eq!(label(entity, &names).as_slice(), "0");
+    }
+
+    #[test]
+    fn set_name_is_not_a_change() {
+        let mut mentity_map = MetaEntityMap::new();
+        let entity = mentity_map.create();
+        mentity_map.events.drain();
+
+        mentity_map.set_name(entity, Some("bullet".to_string()));
+        mentity_map.set_name(entity, None);
+        assert!(mentity_map.events.is_empty());
+    }
+
+    #[test]
+    fn debug_names_of_removed_entities() {
+        let mut mentity_map = MetaEntityMap::new();
+        let entity = mentity_map.create();
+        mentity_map.set_name(entity, Some("bullet".to_string()));
+        mentity_map.remove(entity);
+        mentity_map.drain_events_with(|_| {});
+
+        assert_eq!(label(entity, &mentity_map.names).as_slice(), "0 (bullet)");
+        assert_eq!(mentity_map.create(), entity);
+        assert_eq!(label(entity, &mentity_map.names).as_slice(), "0");
+    }
+
     #[test]
     fn pool_get() {
         let mut pool = Pool::new();
@@ -588,4 +1709,204 @@ mod tests {
         assert_eq!(pool.get().entity, 0);
         assert_eq!(pool.get().entity, 2);
     }
+
+    #[test]
+    fn pool_get_batch() {
+        let mut pool = Pool::new();
+        let recycled = pool.get();
+        pool.get();
+        pool.put(recycled);
+
+        let batch: Vec<_> = pool.get_batch(3).into_iter().map(|mentity| mentity.entity).collect();
+        assert_eq!(batch, vec![0, 2, 3]);
+        assert_eq!(pool.get().entity, 4);
+    }
+
+    #[test]
+    fn pool_reclaim() {
+        let mut pool = Pool::new();
+        let reserver = EntityReserver::new(pool.ids.clone());
+
+        let reservation = reserver.reserve();
+        assert_eq!(reservation.entity(), 0);
+        assert_eq!(pool.get().entity, 1);
+        drop(reservation);
+        assert_eq!(pool.get().entity, 0);
+        assert_eq!(pool.get().entity, 2);
+    }
+
+    #[test]
+    fn create_remove_entities() {
+        let mut em = EntityMapper::new();
+        let entities: Vec<Entity> = em.create_entities(3).collect();
+
+        assert_eq!(entities, vec![0, 1, 2]);
+        assert_eq!(em.mentities.events.events.len(), 3);
+
+        em.remove_entities(entities.iter().map(|e| *e).take(2));
+        assert_eq!(em.mentities.events.events.len(), 5);
+        assert_eq!(em.mentities.events.events.pop_back(), Some((EventKind::Removed, 1)));
+    }
+
+    #[test]
+    fn remove_entities_with_relations() {
+        let mut em = EntityMapper::new();
+        let entities: Vec<Entity> = em.create_entities(3).collect();
+        em.set_group(entities[0], "group");
+        em.set_tag(entities[1], "tag").unwrap();
+        em.relate::<Owns>(entities[2], entities[0]);
+        em.relate::<Owns>(entities[2], entities[1]);
+        em.despawn_after(entities[1], 1.);
+
+        em.remove_entities(entities.iter().map(|e| *e).take(2));
+        assert!(em.get_group("group").is_empty());
+        assert!(em.try_get_tag("tag").is_none());
+        assert!(em.related::<Owns>(entities[2]).is_empty());
+        assert!(em.get_mentity(entities[2]).relations.is_empty());
+        assert!(!em.lifetimes.contains(entities[1]));
+    }
+
+    #[test]
+    fn create_reserved() {
+        let mut em = EntityMapper::new();
+        let reservation = em.reserver().reserve();
+        let entity = reservation.entity();
+
+        assert!(!em.mentities.contains(entity));
+        assert!(em.create_entity() != entity);
+        assert_eq!(em.create_reserved(reservation), entity);
+        assert!(em.get_mentity(entity).is_awake);
+    }
+
+    #[test]
+    #[should_fail]
+    fn create_reserved_from_another_mapper() {
+        let mut em = EntityMapper::new();
+        let other = EntityMapper::new();
+        em.create_reserved(other.reserver().reserve());
+    }
+
+    #[test]
+    fn despawn_after() {
+        let mut em = EntityMapper::new();
+        let mut cm = ComponentMapper::new();
+        let entity = em.create_entity();
+        let cancelled = em.create_entity();
+        let ticked = em.create_entity();
+        em.despawn_after(entity, 1.);
+        em.despawn_after(cancelled, 1.);
+        em.despawn_after_ticks(ticked, 2);
+        assert!(em.cancel_despawn(cancelled));
+
+        em.update_lifetimes(&mut cm, 0.5);
+        em.fixed_update_lifetimes(&mut cm);
+        assert_eq!(em.entities(), vec![entity, cancelled, ticked]);
+
+        em.update_lifetimes(&mut cm, 0.5);
+        em.fixed_update_lifetimes(&mut cm);
+        assert_eq!(em.entities(), vec![cancelled]);
+        assert!(!em.cancel_despawn(cancelled));
+    }
+
+    #[test]
+    fn despawn_after_with() {
+        let mut em = EntityMapper::new();
+        let mut cm = ComponentMapper::new();
+        let entity = em.create_entity();
+        let expired = Rc::new(Cell::new(false));
+
+        let flag = expired.clone();
+        em.despawn_after_with(entity, 1., FromFn::from_fn(
+            move |em: &mut EntityMapper, _: &mut ComponentMapper| {
+                assert!(em.get_mentity(entity).is_awake);
+                flag.set(true);
+            }
+        ));
+        em.update_lifetimes(&mut cm, 1.);
+        assert!(expired.get());
+        assert!(em.entities().is_empty());
+    }
+
+    #[test]
+    fn despawn_again_on_expiry() {
+        let mut em = EntityMapper::new();
+        let mut cm = ComponentMapper::new();
+        let entity = em.create_entity();
+
+        em.despawn_after_ticks_with(entity, 1, FromFn::from_fn(
+            move |em: &mut EntityMapper, _: &mut ComponentMapper| em.despawn_after_ticks(entity, 1)
+        ));
+        em.fixed_update_lifetimes(&mut cm);
+        assert_eq!(em.entities(), vec![entity]);
+        em.fixed_update_lifetimes(&mut cm);
+        assert!(em.entities().is_empty());
+    }
+
+    #[test]
+    fn cancel_despawn_on_expiry() {
+        let mut em = EntityMapper::new();
+        let mut cm = ComponentMapper::new();
+        let first = em.create_entity();
+        let second = em.create_entity();
+
+        em.despawn_after_with(first, 1., FromFn::from_fn(
+            move |em: &mut EntityMapper, _: &mut ComponentMapper| { em.cancel_despawn(second); }
+        ));
+        em.despawn_after(second, 1.);
+        em.update_lifetimes(&mut cm, 1.);
+        assert_eq!(em.entities(), vec![second]);
+
+        em.update_lifetimes(&mut cm, 1.);
+        assert_eq!(em.entities(), vec![second]);
+    }
+
+    #[test]
+    #[should_fail]
+    fn despawn_missing_entity() {
+        let mut em = EntityMapper::new();
+        em.despawn_after(0, 1.);
+    }
+
+    #[test]
+    #[should_fail]
+    fn despawn_after_nan() {
+        let mut em = EntityMapper::new();
+        let entity = em.create_entity();
+        em.despawn_after(entity, Float::nan());
+    }
+
+    struct Owns;
+    impl Relation for Owns {}
+
+    #[test]
+    fn relations_of_removed_entities() {
+        let mut em = EntityMapper::new();
+        let owner = em.create_entity();
+        let first = em.create_entity();
+        let second = em.create_entity();
+        em.relate::<Owns>(owner, first);
+        em.relate::<Owns>(owner, second);
+
+        em.remove_entity(first);
+        assert_eq!(em.related::<Owns>(owner), vec![second]);
+        assert!(em.inverse::<Owns>(first).is_empty());
+        assert!(!em.get_mentity(owner).relations.is_empty());
+
+        em.remove_entity(second);
+        assert!(em.related::<Owns>(owner).is_empty());
+        assert!(em.get_mentity(owner).relations.is_empty());
+
+        let other = em.create_entity();
+        em.relate::<Owns>(other, owner);
+        em.remove_entity(other);
+        assert!(em.inverse::<Owns>(owner).is_empty());
+    }
+
+    #[test]
+    #[should_fail]
+    fn relate_to_missing_entity() {
+        let mut em = EntityMapper::new();
+        let entity = em.create_entity();
+        em.relate::<Owns>(entity, 1);
+    }
 }
@@ -0,0 +1,116 @@
+//! References between entities.
+//!
+//! A component can refer to other entities using `EntityRef`s.
+//! Each reference carries a `RefPolicy` telling what happens to the referrer
+//! when the referenced entity is removed.
+
+use entity::Entity;
+
+/// What happens to a referrer when the referenced entity is removed.
+#[derive(Copy, Clone, PartialEq, Eq, Show)]
+pub enum RefPolicy {
+    /// The reference is nulled and the referrer is notified as changed.
+    Nullify,
+    /// The referrer is removed as well.
+    Cascade
+}
+
+/// A reference to an entity, invalidated when the entity is removed.
+///
+/// This is only effective for components inserted with `ComponentMapper::insert_referrer`.
+#[derive(Copy, Clone, PartialEq, Eq, Show)]
+pub struct EntityRef {
+    target: Option<Entity>,
+    policy: RefPolicy
+}
+
+impl EntityRef {
+    /// Creates a reference to `target` with the given policy.
+    pub fn new(target: Entity, policy: RefPolicy) -> EntityRef {
+        EntityRef {
+            target: Some(target),
+            policy: policy
+        }
+    }
+
+    /// Creates a null reference with the given policy.
+    pub fn null(policy: RefPolicy) -> EntityRef {
+        EntityRef {
+            target: None,
+            policy: policy
+        }
+    }
+
+    /// Returns the referenced entity, if any.
+    pub fn get(&self) -> Option<Entity> {
+        self.target
+    }
+
+    /// Returns the referenced entity.
+    ///
+    /// Panics if the reference is null.
+    pub fn unwrap(&self) -> Entity {
+        self.target.expect("the entity reference is null")
+    }
+
+    /// Returns `true` if the reference is null.
+    pub fn is_null(&self) -> bool {
+        self.target.is_none()
+    }
+
+    /// Returns the policy of the reference.
+    pub fn policy(&self) -> RefPolicy {
+        self.policy
+    }
+
+    /// Makes the reference point to another entity.
+    ///
+    /// Don't forget to call `ComponentMapper::update_refs` afterwards.
+    pub fn set(&mut self, target: Entity) {
+        self.target = Some(target);
+    }
+
+    /// Nulls the reference.
+    pub fn nullify(&mut self) {
+        self.target = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new() {
+        let entity_ref = EntityRef::new(3, RefPolicy::Cascade);
+
+        assert_eq!(entity_ref.get(), Some(3));
+        assert_eq!(entity_ref.unwrap(), 3);
+        assert_eq!(entity_ref.policy(), RefPolicy::Cascade);
+        assert!(!entity_ref.is_null());
+    }
+
+    #[test]
+    fn null() {
+        let entity_ref = EntityRef::null(RefPolicy::Nullify);
+
+        assert!(entity_ref.get().is_none());
+        assert!(entity_ref.is_null());
+    }
+
+    #[test]
+    #[should_fail]
+    fn unwrap_null() {
+        EntityRef::null(RefPolicy::Nullify).unwrap();
+    }
+
+    #[test]
+    fn set_and_nullify() {
+        let mut entity_ref = EntityRef::null(RefPolicy::Nullify);
+
+        entity_ref.set(5);
+        assert_eq!(entity_ref.get(), Some(5));
+        entity_ref.nullify();
+        assert!(entity_ref.is_null());
+    }
+}
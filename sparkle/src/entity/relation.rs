@@ -0,0 +1,221 @@
+//! Relations between entities.
+//!
+//! A relation is a directed edge between two entities, typed by a `Relation`.
+//! An entity can be related to multiple entities by the same type of relation.
+
+use std::any::TypeId;
+use std::collections::{HashMap, HashSet};
+
+use entity::{Entity, MetaEntity};
+
+/// The trait for types of relations.
+///
+/// Relation types are usually simple markers:
+///
+/// ```ignore
+/// struct Owns;
+/// impl Relation for Owns {}
+/// ```
+pub trait Relation: 'static {}
+
+/// The edges of a relation type, in both directions.
+struct Edges {
+    forward: HashMap<Entity, HashSet<Entity>>,
+    backward: HashMap<Entity, HashSet<Entity>>
+}
+
+impl Edges {
+    /// Creates an empty `Edges`.
+    fn new() -> Edges {
+        Edges {
+            forward: HashMap::new(),
+            backward: HashMap::new()
+        }
+    }
+}
+
+/// Inserts `value` in the set mapped to `key`, creating the set if necessary.
+fn link(map: &mut HashMap<Entity, HashSet<Entity>>, key: Entity, value: Entity) {
+    if !map.contains_key(&key) {
+        map.insert(key, HashSet::new());
+    }
+    map.get_mut(&key).unwrap().insert(value);
+}
+
+/// Removes `value` from the set mapped to `key`, removing the set if it becomes empty.
+///
+/// Returns `true` if the set was removed.
+fn unlink(map: &mut HashMap<Entity, HashSet<Entity>>, key: Entity, value: Entity) -> bool {
+    let is_empty = match map.get_mut(&key) {
+        Some(set) => {
+            set.remove(&value);
+            set.is_empty()
+        },
+        None => return false
+    };
+
+    if is_empty {
+        map.remove(&key);
+    }
+    is_empty
+}
+
+/// A `RelationMap` is keeping track of relations between entities.
+pub struct RelationMap {
+    relations: HashMap<TypeId, Edges>
+}
+
+impl RelationMap {
+    /// Creates an empty `RelationMap`.
+    pub fn new() -> RelationMap {
+        RelationMap {
+            relations: HashMap::new()
+        }
+    }
+
+    /// Relates an entity to another one.
+    pub fn relate<R>(&mut self, mentity: &mut MetaEntity, to: Entity)
+        where R: Relation
+    {
+        let kind = TypeId::of::<R>();
+        mentity.relations.insert(kind);
+
+        if !self.relations.contains_key(&kind) {
+            self.relations.insert(kind, Edges::new());
+        }
+        let edges = self.relations.get_mut(&kind).unwrap();
+        link(&mut edges.forward, mentity.entity, to);
+        link(&mut edges.backward, to, mentity.entity);
+    }
+
+    /// Removes the relation between an entity and another one.
+    pub fn unrelate<R>(&mut self, mentity: &mut MetaEntity, to: Entity)
+        where R: Relation
+    {
+        let kind = TypeId::of::<R>();
+
+        if let Some(edges) = self.relations.get_mut(&kind) {
+            unlink(&mut edges.backward, to, mentity.entity);
+            if unlink(&mut edges.forward, mentity.entity, to) {
+                mentity.relations.remove(&kind);
+            }
+        }
+    }
+
+    /// Returns the entities an entity is related to.
+    pub fn related<R>(&self, from: Entity) -> Vec<Entity>
+        where R: Relation
+    {
+        self.relations.get(&TypeId::of::<R>())
+                      .and_then(|edges| edges.forward.get(&from))
+                      .map(|set| set.iter().map(|entity| *entity).collect())
+                      .unwrap_or_else(|| Vec::new())
+    }
+
+    /// Returns the entities related to an entity.
+    pub fn inverse<R>(&self, to: Entity) -> Vec<Entity>
+        where R: Relation
+    {
+        self.relations.get(&TypeId::of::<R>())
+                      .and_then(|edges| edges.backward.get(&to))
+                      .map(|set| set.iter().map(|entity| *entity).collect())
+                      .unwrap_or_else(|| Vec::new())
+    }
+}
+
+#[doc(hidden)]
+pub mod private {
+    use std::any::TypeId;
+
+    use super::{RelationMap, unlink};
+    use entity::{Entity, MetaEntity};
+
+    /// Forgets an entity, removing all of its relations from the `RelationMap`
+    /// without touching the meta entity data.
+    ///
+    /// Returns the entities which lost their last relation of a type,
+    /// along with this type.
+    pub fn forget(relation_map: &mut RelationMap, mentity: &MetaEntity) -> Vec<(Entity, TypeId)> {
+        let entity = mentity.entity;
+        let mut emptied = Vec::new();
+
+        for (kind, edges) in relation_map.relations.iter_mut() {
+            if let Some(targets) = edges.forward.remove(&entity) {
+                for &target in targets.iter() {
+                    unlink(&mut edges.backward, target, entity);
+                }
+            }
+            if let Some(sources) = edges.backward.remove(&entity) {
+                for &source in sources.iter() {
+                    if unlink(&mut edges.forward, source, entity) {
+                        emptied.push((source, *kind));
+                    }
+                }
+            }
+        }
+
+        emptied
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::TypeId;
+
+    use super::{RelationMap, Relation};
+    use entity::MetaEntity;
+
+    struct Owns;
+    impl Relation for Owns {}
+
+    struct Targets;
+    impl Relation for Targets {}
+
+    #[test]
+    fn relate() {
+        let mut relation_map = RelationMap::new();
+        let owner = &mut MetaEntity::new(0);
+
+        relation_map.relate::<Owns>(owner, 1);
+        relation_map.relate::<Owns>(owner, 2);
+
+        let mut related = relation_map.related::<Owns>(0);
+        related.sort();
+        assert_eq!(related, vec![1, 2]);
+        assert_eq!(relation_map.inverse::<Owns>(1), vec![0]);
+        assert!(owner.relations.contains(&TypeId::of::<Owns>()));
+        assert!(relation_map.related::<Targets>(0).is_empty());
+    }
+
+    #[test]
+    fn unrelate() {
+        let mut relation_map = RelationMap::new();
+        let owner = &mut MetaEntity::new(0);
+
+        relation_map.relate::<Owns>(owner, 1);
+        relation_map.relate::<Owns>(owner, 2);
+        relation_map.unrelate::<Owns>(owner, 1);
+
+        assert_eq!(relation_map.related::<Owns>(0), vec![2]);
+        assert!(relation_map.inverse::<Owns>(1).is_empty());
+        assert!(owner.relations.contains(&TypeId::of::<Owns>()));
+
+        relation_map.unrelate::<Owns>(owner, 2);
+        assert!(owner.relations.is_empty());
+    }
+
+    #[test]
+    fn forget() {
+        let mut relation_map = RelationMap::new();
+        let owner = &mut MetaEntity::new(0);
+        let item = &mut MetaEntity::new(1);
+
+        relation_map.relate::<Owns>(owner, 1);
+        relation_map.relate::<Targets>(item, 0);
+
+        let emptied = super::private::forget(&mut relation_map, item);
+        assert_eq!(emptied, vec![(0, TypeId::of::<Owns>())]);
+        assert!(relation_map.related::<Owns>(0).is_empty());
+        assert!(relation_map.inverse::<Targets>(0).is_empty());
+    }
+}
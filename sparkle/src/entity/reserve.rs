@@ -0,0 +1,208 @@
+//! Reservation of entity identifiers.
+//!
+//! An `EntityReserver` can be cloned and sent to other threads
+//! to reserve entity identifiers before the entities are actually created.
+//! Each `Reservation` is then materialized with `EntityMapper::create_reserved`,
+//! typically from a command:
+//!
+//! ```ignore
+//! let reserver = space.em.reserver();
+//! // On the loading thread:
+//! let reservation = reserver.reserve();
+//! let entity = reservation.entity();
+//! sender.send(Box::new(SpawnTree { reservation: Some(reservation) }));
+//! // `entity` can be used right away, for instance in other commands.
+//! ```
+//!
+//! A reservation dropped without being materialized is reclaimed,
+//! its identifier being reused by the `EntityMapper`.
+
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use entity::Entity;
+
+/// Allocates entity identifiers, possibly from multiple threads.
+pub struct IdAllocator {
+    next_id: AtomicUsize,
+    released_count: AtomicUsize,
+    released: Mutex<Vec<Entity>>
+}
+
+impl IdAllocator {
+    /// Creates a new `IdAllocator`.
+    pub fn new() -> IdAllocator {
+        IdAllocator {
+            next_id: AtomicUsize::new(0),
+            released_count: AtomicUsize::new(0),
+            released: Mutex::new(Vec::new())
+        }
+    }
+
+    /// Allocates a never used identifier.
+    pub fn next(&self) -> Entity {
+        self.next_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// Allocates `count` never used identifiers at once.
+    pub fn next_range(&self, count: usize) -> Range<Entity> {
+        let first = self.next_id.fetch_add(count, Ordering::SeqCst);
+        first..first + count
+    }
+
+    /// Returns the number of allocated identifiers.
+    pub fn count(&self) -> usize {
+        self.next_id.load(Ordering::SeqCst)
+    }
+
+    /// Releases an identifier which was reserved but never used.
+    ///
+    /// The count is only updated while the lock is held, so that it never lags behind.
+    fn release(&self, entity: Entity) {
+        let mut released = self.released.lock().unwrap();
+        released.push(entity);
+        self.released_count.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Takes the released identifiers, to be reused.
+    pub fn take_released(&self) -> Vec<Entity> {
+        if self.released_count.load(Ordering::SeqCst) == 0 {
+            return Vec::new();
+        }
+
+        let mut released = self.released.lock().unwrap();
+        self.released_count.store(0, Ordering::SeqCst);
+        released.drain().collect()
+    }
+}
+
+/// Reserves entity identifiers from outside the `EntityMapper`.
+///
+/// This is thread safe and can be cloned.
+#[derive(Clone)]
+pub struct EntityReserver {
+    ids: Arc<IdAllocator>
+}
+
+impl EntityReserver {
+    /// Creates an `EntityReserver` using the given allocator.
+    #[doc(hidden)]
+    pub fn new(ids: Arc<IdAllocator>) -> EntityReserver {
+        EntityReserver {
+            ids: ids
+        }
+    }
+
+    /// Reserves an entity identifier.
+    pub fn reserve(&self) -> Reservation {
+        Reservation {
+            entity: self.ids.next(),
+            ids: self.ids.clone(),
+            is_materialized: false
+        }
+    }
+}
+
+/// A reserved entity identifier.
+///
+/// If dropped before being materialized, the identifier is reclaimed.
+pub struct Reservation {
+    entity: Entity,
+    ids: Arc<IdAllocator>,
+    is_materialized: bool
+}
+
+impl Reservation {
+    /// Returns the reserved entity identifier.
+    pub fn entity(&self) -> Entity {
+        self.entity
+    }
+
+    /// Returns `true` if the identifier was reserved from the given allocator.
+    #[doc(hidden)]
+    pub fn is_from(&self, ids: &Arc<IdAllocator>) -> bool {
+        &*self.ids as *const IdAllocator == &**ids as *const IdAllocator
+    }
+
+    /// Marks the reservation as materialized, returning the entity identifier.
+    #[doc(hidden)]
+    pub fn materialize(mut self) -> Entity {
+        self.is_materialized = true;
+        self.entity
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if !self.is_materialized {
+            self.ids.release(self.entity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread::Thread;
+
+    use super::{IdAllocator, EntityReserver};
+
+    #[test]
+    fn reserve() {
+        let ids = Arc::new(IdAllocator::new());
+        let reserver = EntityReserver::new(ids.clone());
+
+        assert_eq!(reserver.reserve().materialize(), 0);
+        assert_eq!(reserver.reserve().materialize(), 1);
+        assert_eq!(ids.next(), 2);
+        assert!(ids.take_released().is_empty());
+    }
+
+    #[test]
+    fn reclaim() {
+        let ids = Arc::new(IdAllocator::new());
+        let reserver = EntityReserver::new(ids.clone());
+
+        {
+            let reservation = reserver.reserve();
+            assert_eq!(reservation.entity(), 0);
+        }
+        assert_eq!(ids.take_released(), vec![0]);
+        assert!(ids.take_released().is_empty());
+    }
+
+    #[test]
+    fn reclaim_from_threads() {
+        let ids = Arc::new(IdAllocator::new());
+        let reserver = EntityReserver::new(ids.clone());
+
+        let guards: Vec<_> = range(0, 4us).map(|_| {
+            let reserver = reserver.clone();
+            Thread::scoped(move || {
+                for _ in range(0, 100us) {
+                    reserver.reserve();
+                }
+            })
+        }).collect();
+
+        let mut taken = Vec::new();
+        while taken.len() < 400 {
+            taken.extend(ids.take_released().into_iter());
+        }
+        drop(guards);
+
+        taken.sort();
+        assert_eq!(taken, range(0, 400us).collect::<Vec<_>>());
+        assert!(ids.take_released().is_empty());
+    }
+
+    #[test]
+    fn is_from() {
+        let ids = Arc::new(IdAllocator::new());
+        let reservation = EntityReserver::new(ids.clone()).reserve();
+
+        assert!(reservation.is_from(&ids));
+        assert!(!reservation.is_from(&Arc::new(IdAllocator::new())));
+    }
+}
@@ -1,49 +1,110 @@
 //! Identification of entities using tags.
 //!
-//! A tag has a name and can only be applied to one entity at a time.  
-//! An entity can only have one tag.
+//! A tag has a name and can only be applied to one entity at a time.
+//! An entity can have multiple tags.
+//!
+//! Tags are sorted in namespaces, so that two tags with the same name
+//! but different namespaces are distinct.
+//!
+//! Tags are interned, being handled through `TagId`s.
 
-use std::mem;
-use std::collections::HashMap;
+use std::collections::VecMap;
 
 use entity::{Entity, MetaEntity};
+use entity::intern::TagId;
+
+/// The namespace of tags declared without any namespace.
+pub const DEFAULT_NAMESPACE: &'static str = "";
+
+/// A tag name, qualified by its namespace.
+#[derive(Clone, PartialEq, Eq, Hash, Show)]
+pub struct Tag {
+    pub namespace: String,
+    pub name: String
+}
+
+impl Tag {
+    /// Creates a tag in the default namespace.
+    pub fn new(name: &str) -> Tag {
+        Tag::in_namespace(DEFAULT_NAMESPACE, name)
+    }
+
+    /// Creates a tag in the given namespace.
+    pub fn in_namespace(namespace: &str, name: &str) -> Tag {
+        Tag {
+            namespace: namespace.to_string(),
+            name: name.to_string()
+        }
+    }
+}
+
+/// What to do when a tag is already used by another entity.
+#[derive(Copy, Clone, PartialEq, Eq, Show)]
+pub enum TagPolicy {
+    /// The tagging fails, nothing is changed.
+    Fail,
+    /// The tag is taken from the other entity.
+    Steal,
+    /// The tag is taken from the other entity, which receives in exchange
+    /// the tags of the same namespace previously held by the tagged entity.
+    Swap
+}
+
+/// The error returned when a tag is already used by another entity.
+#[derive(Clone, PartialEq, Eq, Show)]
+pub struct TagConflict {
+    /// The conflicting tag.
+    pub tag: Tag,
+    /// The entity holding the tag.
+    pub holder: Entity
+}
 
 /// A `TagMap` is keeping track of entity tags.
 pub struct TagMap {
-    tags: HashMap<String, Entity>
+    tags: VecMap<Entity>
 }
 
 impl TagMap {
     /// Creates an empty `TagMap`.
     pub fn new() -> TagMap {
         TagMap {
-            tags: HashMap::new()
+            tags: VecMap::new()
         }
     }
 
     /// Inserts an entity tag.
     ///
-    /// If the entity was already tagged, the previous tag will be overriden and returned.
-    /// Panics if the tag was already used.
-    pub fn insert(&mut self, mentity: &mut MetaEntity, tag: &str) -> Option<String> {
-        let previous_tag = mem::replace(&mut mentity.tag, Some(tag.to_string()));
-        previous_tag.as_ref().map(|t| self.tags.remove(t));
-        
-        if let Some(already_tagged) = self.tags.insert(tag.to_string(), mentity.entity) {
-            panic!("the tag '{}' was already tagging entity {}", tag, already_tagged);
+    /// Fails without changing anything if the tag was already used by another entity,
+    /// returning the entity holding the tag.
+    pub fn insert(&mut self, mentity: &mut MetaEntity, tag: TagId) -> Result<(), Entity> {
+        if let Some(&holder) = self.tags.get(&tag.0) {
+            if holder != mentity.entity {
+                return Err(holder);
+            }
         }
-        
-        previous_tag
+
+        self.tags.insert(tag.0, mentity.entity);
+        mentity.tags.insert(tag);
+        Ok(())
     }
 
-    /// Removes the tag of an entity.
-    pub fn remove(&mut self, mentity: &mut MetaEntity) {
-        mentity.tag.take().map(|tag| self.tags.remove(&tag));
+    /// Removes a tag of an entity.
+    pub fn remove(&mut self, mentity: &mut MetaEntity, tag: TagId) {
+        if mentity.tags.remove(&tag) {
+            self.tags.remove(&tag.0);
+        }
+    }
+
+    /// Clears an entity tags.
+    pub fn clear_entity(&mut self, mentity: &mut MetaEntity) {
+        for tag in mentity.tags.drain() {
+            self.tags.remove(&tag.0);
+        }
     }
 
     /// Returns the entity tagged by `tag` if it exists.
-    pub fn get(&self, tag: &str) -> Option<Entity> {
-        self.tags.get(tag).map(|entity| *entity)
+    pub fn get(&self, tag: TagId) -> Option<Entity> {
+        self.tags.get(&tag.0).map(|entity| *entity)
     }
 }
 
@@ -55,7 +116,9 @@ pub mod private {
     /// Forgets an entity, removing it from the `TagMap`
     /// without touching the meta entity data.
     pub fn forget(tag_map: &mut TagMap, mentity: &MetaEntity) {
-        mentity.tag.as_ref().map(|tag| tag_map.tags.remove(tag));
+        for tag in mentity.tags.iter() {
+            tag_map.tags.remove(&tag.0);
+        }
     }
 }
 
@@ -63,72 +126,102 @@ pub mod private {
 mod tests {
     use super::*;
     use MetaEntity;
+    use entity::intern::TagId;
     
     #[test]
     fn insertion() {
         let mut tag_map = TagMap::new();
         let entity = &mut MetaEntity::new(0);
-        let tag = "tag";
+        let tag = TagId(0);
         
-        tag_map.insert(entity, tag);
-        assert_eq!(tag_map.tags.get(tag), Some(&entity.entity));
-        assert_eq!(entity.tag.as_ref().map(|t| t.as_slice()), Some(tag));
+        tag_map.insert(entity, tag).unwrap();
+        assert_eq!(tag_map.tags.get(&tag.0), Some(&entity.entity));
+        assert!(entity.tags.contains(&tag));
     }
     
     #[test]
-    #[should_fail]
     fn double_tag_insertion() {
         let mut tag_map = TagMap::new();
         let entity = &mut MetaEntity::new(0);
         let other = &mut MetaEntity::new(1);
+        let tag = TagId(0);
         
-        let tag = "tag";
-        tag_map.insert(entity, tag);
-        tag_map.insert(other, tag);
+        tag_map.insert(entity, tag).unwrap();
+        assert_eq!(tag_map.insert(other, tag), Err(entity.entity));
+        assert_eq!(tag_map.get(tag), Some(entity.entity));
+        assert!(entity.tags.contains(&tag));
+        assert!(other.tags.is_empty());
     }
     
     #[test]
     fn double_entity_insertion() {
         let mut tag_map = TagMap::new();
         let entity = &mut MetaEntity::new(0);
-        let tag = "tag";
-        let new_tag = "gat";
+        let tag = TagId(0);
+        let new_tag = TagId(1);
+        
+        tag_map.insert(entity, tag).unwrap();
+        tag_map.insert(entity, new_tag).unwrap();
+        assert_eq!(tag_map.tags.get(&tag.0), Some(&entity.entity));
+        assert_eq!(tag_map.tags.get(&new_tag.0), Some(&entity.entity));
+        assert_eq!(entity.tags.len(), 2);
+    }
+    
+    #[test]
+    fn same_tag_insertion() {
+        let mut tag_map = TagMap::new();
+        let entity = &mut MetaEntity::new(0);
         
-        tag_map.insert(entity, tag);
-        tag_map.insert(entity, new_tag);
-        assert_eq!(tag_map.tags.get(new_tag), Some(&entity.entity));
-        assert_eq!(entity.tag.as_ref().map(|t| t.as_slice()), Some(new_tag));
-        assert!(tag_map.tags.get(tag).is_none());
+        tag_map.insert(entity, TagId(0)).unwrap();
+        tag_map.insert(entity, TagId(0)).unwrap();
+        assert_eq!(entity.tags.len(), 1);
+    }
+    
+    #[test]
+    fn namespaces() {
+        let mut tag_map = TagMap::new();
+        let entity = &mut MetaEntity::new(0);
+        let other = &mut MetaEntity::new(1);
+        let tag = TagId(0);
+        let other_tag = TagId(1);
+        
+        tag_map.insert(entity, tag).unwrap();
+        tag_map.insert(other, other_tag).unwrap();
+        assert_eq!(tag_map.get(tag), Some(entity.entity));
+        assert_eq!(tag_map.get(other_tag), Some(other.entity));
+        assert!(tag_map.get(TagId(2)).is_none());
     }
     
     #[test]
     fn get() {
         let mut tag_map = TagMap::new();
         let entity = &mut MetaEntity::new(0);
-        let tag = "tag";
+        let tag = TagId(0);
         
-        tag_map.insert(entity, tag);
+        tag_map.insert(entity, tag).unwrap();
         assert_eq!(tag_map.get(tag), Some(entity.entity));
     }
     
     #[test]
     fn get_nonexistent() {
         let tag_map = TagMap::new();
-        let tag = "tag";
         
-        assert!(tag_map.get(tag).is_none());
+        assert!(tag_map.get(TagId(0)).is_none());
     }
     
     #[test]
     fn removal() {
         let mut tag_map = TagMap::new();
         let entity = &mut MetaEntity::new(0);
-        let tag = "tag";
+        let tag = TagId(0);
+        let other_tag = TagId(1);
         
-        tag_map.insert(entity, tag);
-        tag_map.remove(entity);
-        assert!(tag_map.tags.get(tag).is_none());
-        assert!(entity.tag.is_none());
+        tag_map.insert(entity, tag).unwrap();
+        tag_map.insert(entity, other_tag).unwrap();
+        tag_map.remove(entity, tag);
+        assert!(tag_map.tags.get(&tag.0).is_none());
+        assert!(!entity.tags.contains(&tag));
+        assert!(entity.tags.contains(&other_tag));
     }
     
     #[test]
@@ -136,20 +229,44 @@ mod tests {
         let mut tag_map = TagMap::new();
         let entity = &mut MetaEntity::new(0);
         
-        tag_map.remove(entity);
-        assert!(entity.tag.is_none());
+        tag_map.remove(entity, TagId(0));
+        assert!(entity.tags.is_empty());
+    }
+    
+    #[test]
+    fn removal_of_another_entity_tag() {
+        let mut tag_map = TagMap::new();
+        let entity = &mut MetaEntity::new(0);
+        let other = &mut MetaEntity::new(1);
+        let tag = TagId(0);
+        
+        tag_map.insert(entity, tag).unwrap();
+        tag_map.remove(other, tag);
+        assert_eq!(tag_map.get(tag), Some(entity.entity));
+    }
+    
+    #[test]
+    fn clear_entity() {
+        let mut tag_map = TagMap::new();
+        let entity = &mut MetaEntity::new(0);
+        
+        tag_map.insert(entity, TagId(0)).unwrap();
+        tag_map.insert(entity, TagId(1)).unwrap();
+        tag_map.clear_entity(entity);
+        assert!(tag_map.tags.is_empty());
+        assert!(entity.tags.is_empty());
     }
     
     #[test]
     fn forgetting() {
         let mut tag_map = &mut TagMap::new();
         let entity = &mut MetaEntity::new(0);
-        let tag = "tag";
+        let tag = TagId(0);
         
-        tag_map.insert(entity, tag);
+        tag_map.insert(entity, tag).unwrap();
         private::forget(tag_map, entity);
-        assert!(tag_map.tags.get(tag).is_none());
-        assert_eq!(entity.tag.as_ref().map(|t| t.as_slice()), Some(tag));
+        assert!(tag_map.tags.get(&tag.0).is_none());
+        assert!(entity.tags.contains(&tag));
     }
     
     #[test]
@@ -158,6 +275,6 @@ mod tests {
         let entity = &mut MetaEntity::new(0);
         
         private::forget(tag_map, entity);
-        assert!(entity.tag.is_none());
+        assert!(entity.tags.is_empty());
     }
 }
@@ -0,0 +1,257 @@
+//! The event related features.
+//!
+//! Systems can communicate through typed events sent on the `EventBus` of the `Space`,
+//! which they are given by `System::on_inserted`:
+//!
+//! ````ignore
+//! fn on_inserted(&mut self, events: &EventBus) {
+//!     self.reader = Some(events.reader());
+//! }
+//!
+//! // In the collision system:
+//! events.send(Collision { a: bullet, b: ship });
+//!
+//! // In the damage system, which owns its own reader:
+//! for collision in reader.read::<Collision>().into_iter() {
+//!     // ...
+//! }
+//! ````
+//!
+//! Each reader keeps its own cursor, so every reader sees every event once.
+//! Events are double-buffered: they are dropped once every reader has seen them,
+//! or at the latest after two updates of the `SystemMapper`.
+
+use std::collections::HashMap;
+use std::any::{Any, TypeId};
+use std::rc::{Rc, Weak};
+use std::cell::{Cell, RefCell};
+use std::mem;
+
+/// The trait for events.
+pub trait Event: Clone + 'static {}
+
+impl<E> Event for E where E: Clone + 'static {}
+
+/// A bus of typed events, shared by the systems of a `Space`.
+///
+/// The bus can be cloned, the clones sharing the same events.
+#[derive(Clone)]
+pub struct EventBus {
+    channels: Rc<RefCell<HashMap<TypeId, Box<AnyChannel>>>>
+}
+
+impl EventBus {
+    /// Creates an empty `EventBus`.
+    pub fn new() -> EventBus {
+        EventBus {
+            channels: Rc::new(RefCell::new(HashMap::new()))
+        }
+    }
+
+    /// Sends an event to every reader.
+    pub fn send<E>(&self, event: E)
+        where E: Event
+    {
+        self.with_channel(|channel: &mut Channel<E>| channel.current.push(event));
+    }
+
+    /// Creates a new reader of this bus, with its own cursors.
+    pub fn reader(&self) -> EventReader {
+        EventReader {
+            bus: self.clone(),
+            cursors: HashMap::new()
+        }
+    }
+
+    /// Swaps the event buffers of each type.
+    ///
+    /// The events sent two updates ago are dropped, as well as the ones
+    /// that every reader has already seen.
+    /// This is done by `SystemMapper::update`.
+    pub fn flip(&self) {
+        for channel in self.channels.borrow_mut().values_mut() {
+            channel.flip();
+        }
+    }
+
+    /// Applies `func` to the channel of the events `E`, creating it if necessary.
+    fn with_channel<E, F, R>(&self, func: F) -> R
+        where E: Event, F: FnOnce(&mut Channel<E>) -> R
+    {
+        let mut channels = self.channels.borrow_mut();
+        let channel = channels.entry(TypeId::of::<E>()).get().unwrap_or_else(|vacant| {
+            vacant.insert(Box::new(Channel::<E>::new()))
+        });
+
+        match channel.as_any_mut().downcast_mut::<Channel<E>>() {
+            Some(channel) => func(channel),
+            None => unreachable!()
+        }
+    }
+}
+
+/// Reads events from an `EventBus`.
+///
+/// Each system should own its reader, since a reader only sees each event once.
+pub struct EventReader {
+    bus: EventBus,
+    cursors: HashMap<TypeId, Rc<Cell<usize>>>
+}
+
+impl EventReader {
+    /// Returns the events of type `E` that this reader hasn't seen yet,
+    /// in the order they were sent.
+    ///
+    /// The cursor of a type is created by the first read,
+    /// starting at the oldest event still buffered.
+    pub fn read<E>(&mut self) -> Vec<E>
+        where E: Event
+    {
+        let EventReader { ref bus, ref mut cursors } = *self;
+        bus.with_channel(|channel: &mut Channel<E>| {
+            let cursor = match cursors.get(&TypeId::of::<E>()) {
+                Some(cursor) => cursor.clone(),
+                None => {
+                    let cursor = Rc::new(Cell::new(channel.first));
+                    channel.readers.push(cursor.downgrade());
+                    cursor
+                }
+            };
+            cursors.insert(TypeId::of::<E>(), cursor.clone());
+
+            let events = channel.since(cursor.get());
+            cursor.set(channel.end());
+            events
+        })
+    }
+}
+
+/// Type-erased operations on an event channel.
+trait AnyChannel: 'static {
+    /// Swaps the event buffers.
+    fn flip(&mut self);
+    /// Returns the channel as `Any`, to be downcast.
+    fn as_any_mut(&mut self) -> &mut Any;
+}
+
+/// The double-buffered events of one type.
+struct Channel<E> {
+    /// The events sent before the last flip.
+    previous: Vec<E>,
+    /// The events sent since the last flip.
+    current: Vec<E>,
+    /// The absolute position of the first event of `previous`.
+    first: usize,
+    /// The cursors of the readers, as absolute positions.
+    readers: Vec<Weak<Cell<usize>>>
+}
+
+impl<E> Channel<E>
+    where E: Event
+{
+    /// Creates an empty `Channel`.
+    fn new() -> Channel<E> {
+        Channel {
+            previous: Vec::new(),
+            current: Vec::new(),
+            first: 0,
+            readers: Vec::new()
+        }
+    }
+
+    /// Returns the absolute position following the last event.
+    fn end(&self) -> usize {
+        self.first + self.previous.len() + self.current.len()
+    }
+
+    /// Returns the events from the absolute position `cursor`.
+    fn since(&self, cursor: usize) -> Vec<E> {
+        let skip = if cursor > self.first { cursor - self.first } else { 0 };
+        self.previous.iter().chain(self.current.iter())
+                     .skip(skip)
+                     .map(|event| event.clone())
+                     .collect()
+    }
+}
+
+impl<E> AnyChannel for Channel<E>
+    where E: Event
+{
+    fn flip(&mut self) {
+        self.readers.retain(|reader| reader.upgrade().is_some());
+        let seen = self.readers.iter().filter_map(|reader| reader.upgrade())
+                                      .map(|cursor| cursor.get())
+                                      .min();
+
+        // The previous events are two updates old.
+        self.first += self.previous.len();
+        self.previous = mem::replace(&mut self.current, Vec::new());
+
+        // Drops the events every reader has already seen.
+        // Without any reader, the events are kept for readers to come.
+        if let Some(seen) = seen {
+            if seen > self.first {
+                let count = seen - self.first;
+                self.previous = self.previous.drain().skip(count).collect();
+                self.first = seen;
+            }
+        }
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EventBus;
+
+    #[derive(Clone, PartialEq, Show)]
+    struct Ping(usize);
+
+    #[test]
+    fn readers_at_different_speeds() {
+        let bus = EventBus::new();
+        let mut fast = bus.reader();
+        let mut slow = bus.reader();
+        assert!(fast.read::<Ping>().is_empty());
+        assert!(slow.read::<Ping>().is_empty());
+
+        bus.send(Ping(1));
+        assert_eq!(fast.read::<Ping>(), vec![Ping(1)]);
+        bus.flip();
+
+        bus.send(Ping(2));
+        assert_eq!(fast.read::<Ping>(), vec![Ping(2)]);
+        assert_eq!(slow.read::<Ping>(), vec![Ping(1), Ping(2)]);
+        assert!(slow.read::<Ping>().is_empty());
+        bus.flip();
+
+        // Unread events are dropped after two flips.
+        bus.send(Ping(3));
+        bus.flip();
+        assert_eq!(fast.read::<Ping>(), vec![Ping(3)]);
+        bus.flip();
+        assert!(slow.read::<Ping>().is_empty());
+    }
+
+    #[test]
+    fn late_reader() {
+        let bus = EventBus::new();
+        bus.send(Ping(1));
+        bus.flip();
+        bus.send(Ping(2));
+
+        // Without any reader, the events are kept for readers to come.
+        let mut late = bus.reader();
+        assert_eq!(late.read::<Ping>(), vec![Ping(1), Ping(2)]);
+        bus.flip();
+        bus.send(Ping(3));
+
+        // Once seen by every reader, the events are dropped.
+        let mut later = bus.reader();
+        assert_eq!(later.read::<Ping>(), vec![Ping(3)]);
+        assert_eq!(late.read::<Ping>(), vec![Ping(3)]);
+    }
+}
@@ -7,7 +7,8 @@
 //!
 //! [Entities](entity/index.html) are simple identifiers. However, you can refer to them
 //! by attaching them [tags](entity/index.html#identification-of-entities-using-tags)
-//! and [groups](entity/index.html#identification-of-entities-using-groups).
+//! and [groups](entity/index.html#identification-of-entities-using-groups),
+//! or [relate](entity/index.html#relations-between-entities) them to each other.
 //! All of these informations are contained in [MetaEntities](entity/struct.MetaEntity.html).
 //!
 //! ## Components
@@ -19,8 +20,9 @@
 //!
 //! [Systems](system/index.html) have the only purpose of updating entities
 //! according to their components. Each system can process as many components as they want
-//! and can communicate through the [commands](command/index.html)
-//! and the [blackboard](blackboard/index.html).
+//! and can communicate through the [commands](command/index.html),
+//! the typed [events](event/index.html) and the [blackboard](blackboard/index.html).
+//! The commands can be [recorded](record/index.html) to replay a session.
 //! 
 //! ## Space
 //! 
@@ -28,24 +30,32 @@
 //! a [ComponentMapper](component/struct.ComponentMapper.html)
 //! and a [SystemMapper](system/struct.SystemMapper.html).
 //! Each space represents an independent part of your game world.
+//! It can run its fixed updates on its own, using a [timestep](timestep/index.html).
 
 #![crate_name = "sparkle"]
 #![unstable]
 #![allow(unstable)]
 #![feature(slicing_syntax, unboxed_closures)]
-#![feature(box_syntax, old_impl_check)]
+#![feature(box_syntax, old_impl_check, default_type_params)]
 
 pub use blackboard::{Blackboard, SharedBlackboard, BlackboardEntry};
-pub use space::{Space, SpaceCommand};
+pub use space::{Space, SpaceCommand, SyncSpaceCommand};
+pub use event::{Event, EventBus, EventReader};
+pub use timestep::{Timestep, Interpolation};
 
 pub use component::ComponentMapper;
 
-pub use entity::{Entity, MetaEntity, EntityMapper};
+pub use entity::{Entity, MetaEntity, EntityMapper, EntityRef, RefPolicy, Relation,
+    Tag, TagPolicy, TagConflict, GroupId, TagId, GroupNames,
+    GroupIter, GroupUnion, GroupIntersection, GroupDifference, GroupPattern,
+    EntityReserver, Reservation};
 
-pub use system::{System, SystemMapper,
+pub use system::{System, SystemMapper, EntityCommands,
     EntityView, StandardEntityView, EntityFilter, StandardEntityFilter};
 
-pub use command::{Command, CommandSender, CommandReceiver};
+pub use command::{Command, CommandSender, CommandReceiver, CommandHandle, Reply, Replier,
+    SendError, OverflowPolicy, StreamStats, Priority,
+    SyncCommandSender, SyncCommandReceiver};
 
 pub mod entity;
 pub mod component;
@@ -53,6 +63,9 @@ pub mod system;
 pub mod space;
 pub mod command;
 pub mod blackboard;
+pub mod event;
+pub mod record;
+pub mod timestep;
 
 /// The Sparkle prelude.
 ///
@@ -66,11 +79,15 @@ pub mod blackboard;
 pub mod prelude {
     pub use {
         Blackboard, SharedBlackboard, BlackboardEntry,
-        Space, SpaceCommand,
+        Space, SpaceCommand, SyncSpaceCommand,
+        Event, EventBus, EventReader,
+        Timestep, Interpolation,
         ComponentMapper,
-        Entity, MetaEntity, EntityMapper,
-        System, SystemMapper,
+        Entity, MetaEntity, EntityMapper, EntityRef, RefPolicy, Relation,
+        Tag, TagPolicy, TagConflict, GroupId, TagId, GroupNames,
+        System, SystemMapper, EntityCommands,
         EntityView, StandardEntityView, EntityFilter, StandardEntityFilter,
-        Command, CommandSender, CommandReceiver
+        Command, CommandSender, CommandReceiver, CommandHandle, Reply, Replier, Priority,
+        SyncCommandSender, SyncCommandReceiver
     };
 }
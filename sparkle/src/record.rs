@@ -0,0 +1,535 @@
+//! The command recording and replay features.
+//!
+//! For bug reproduction, the commands run by a `Space` can be recorded
+//! along with the delta time of each frame and a hash of the world at the end of the frame:
+//!
+//! ````ignore
+//! let recorder = Recorder::new();
+//! space.record(recorder.clone());
+//! // ... play ...
+//! try!(recorder.save(&Path::new("session.replay")));
+//! ````
+//!
+//! To be recorded, a command must be encodable, see `Command::encode`.
+//! Only the commands sent from outside of the frames are recorded,
+//! since the ones sent by other commands or by systems are sent again when replaying.
+//! The session can then be replayed headlessly on a space set up the same way,
+//! the space commands being decoded and sent back at the frame they were run:
+//!
+//! ````ignore
+//! let mut decoders = Decoders::new();
+//! decoders.register("spawn", Spawn::decode);
+//! let replay = try!(Replay::load(&Path::new("session.replay")));
+//! match replay.run(&mut space, &decoders) {
+//!     Err(ReplayError::Divergence { frame, .. }) => println!("diverged at frame {}", frame),
+//!     // ...
+//! }
+//! ````
+//!
+//! The recorded commands are sent back to the default channel with the normal priority,
+//! in the order they were run, so their channels and priorities aren't recorded.
+//!
+//! The inter-system commands are sent again by the replayed systems themselves,
+//! they are only recorded to help diagnosing a divergence.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::io::{File, BufferedReader, IoResult, IoError, IoErrorKind};
+use std::mem;
+
+use command::Encoded;
+use space::{Space, SpaceCommand};
+
+/// The stream a command was run from.
+#[derive(Copy, Clone, PartialEq, Eq, Show)]
+pub enum Stream {
+    /// The commands run by `Space::run_commands`.
+    Space,
+    /// The commands run by `SystemMapper::process_commands`.
+    System
+}
+
+/// A recorded entry.
+#[derive(Clone, PartialEq, Show)]
+pub enum Entry {
+    /// The start of a frame driven by `Space::update`, with its delta time.
+    Update(f32),
+    /// The start of a frame driven by `Space::fixed_update`.
+    FixedUpdate,
+    /// A command run during the frame, if it could be encoded.
+    Command(Stream, Option<Encoded>),
+    /// The world hash at the end of the frame.
+    Hash(u64)
+}
+
+impl Entry {
+    /// Formats the entry as a line, without the line break.
+    fn format(&self) -> String {
+        match *self {
+            Entry::Update(dt) => {
+                let bits: u32 = unsafe { mem::transmute(dt) };
+                format!("update {}", bits)
+            },
+            Entry::FixedUpdate => "fixed".to_string(),
+            Entry::Command(stream, ref encoded) => {
+                let stream = match stream {
+                    Stream::Space => "space",
+                    Stream::System => "system"
+                };
+                match *encoded {
+                    Some(ref encoded) => {
+                        format!("{} {} {}", stream, encoded.name, escape(encoded.data.as_slice()))
+                    },
+                    None => format!("{}", stream)
+                }
+            },
+            Entry::Hash(hash) => format!("hash {}", hash)
+        }
+    }
+
+    /// Parses an entry from a line, without the line break.
+    fn parse(line: &str) -> Option<Entry> {
+        let (word, rest) = split_word(line);
+        match word {
+            "update" => rest.parse::<u32>().map(|bits| {
+                Entry::Update(unsafe { mem::transmute(bits) })
+            }),
+            "fixed" => Some(Entry::FixedUpdate),
+            "space" | "system" => {
+                let stream = if word == "space" { Stream::Space } else { Stream::System };
+                if rest.is_empty() {
+                    Some(Entry::Command(stream, None))
+                } else {
+                    let (name, data) = split_word(rest);
+                    if name.is_empty() || name.chars().any(|c| c.is_whitespace()) {
+                        return None;
+                    }
+                    unescape(data).map(|data| {
+                        Entry::Command(stream, Some(Encoded::new(name, data)))
+                    })
+                }
+            },
+            "hash" => rest.parse::<u64>().map(|hash| Entry::Hash(hash)),
+            _ => None
+        }
+    }
+}
+
+/// Splits a line at its first space.
+fn split_word(line: &str) -> (&str, &str) {
+    match line.find(' ') {
+        Some(i) => (&line[..i], &line[i + 1..]),
+        None => (line, "")
+    }
+}
+
+/// Escapes the backslashes and line breaks of the data of a command,
+/// so that it fits on a line.
+fn escape(data: &str) -> String {
+    let mut escaped = String::with_capacity(data.len());
+    for c in data.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c)
+        }
+    }
+    escaped
+}
+
+/// Reverts `escape`, returning `None` if the data isn't properly escaped.
+fn unescape(data: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(data.len());
+    let mut chars = data.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('\\') => unescaped.push('\\'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            _ => return None
+        }
+    }
+    Some(unescaped)
+}
+
+/// Records the entries of a session.
+///
+/// The recorder can be cloned, the clones sharing the same entries.
+#[derive(Clone)]
+pub struct Recorder {
+    entries: Rc<RefCell<Vec<Entry>>>
+}
+
+impl Recorder {
+    /// Creates an empty `Recorder`.
+    pub fn new() -> Recorder {
+        Recorder {
+            entries: Rc::new(RefCell::new(Vec::new()))
+        }
+    }
+
+    /// Records an entry.
+    pub fn record(&self, entry: Entry) {
+        self.entries.borrow_mut().push(entry);
+    }
+
+    /// Returns the recorded entries.
+    pub fn entries(&self) -> Vec<Entry> {
+        self.entries.borrow().clone()
+    }
+
+    /// Saves the recorded entries to a file, one per line.
+    pub fn save(&self, path: &Path) -> IoResult<()> {
+        let mut file = try!(File::create(path));
+        for entry in self.entries.borrow().iter() {
+            try!(file.write_line(entry.format().as_slice()));
+        }
+        Ok(())
+    }
+}
+
+/// Decodes a command from its encoded data.
+pub type Decoder<C> = fn(&str) -> Option<C>;
+
+/// Decoders of commands, registered by name.
+pub struct Decoders<C> {
+    decoders: HashMap<String, Decoder<C>>
+}
+
+impl<C> Decoders<C> {
+    /// Creates an empty `Decoders`.
+    pub fn new() -> Decoders<C> {
+        Decoders {
+            decoders: HashMap::new()
+        }
+    }
+
+    /// Registers the decoder of the commands encoded with the given name.
+    pub fn register(&mut self, name: &str, decoder: Decoder<C>) {
+        self.decoders.insert(name.to_string(), decoder);
+    }
+
+    /// Decodes a command.
+    ///
+    /// Returns `None` if no decoder is registered for the command or if the decoding fails.
+    pub fn decode(&self, encoded: &Encoded) -> Option<C> {
+        self.decoders.get(encoded.name.as_slice())
+                     .and_then(|decoder| (*decoder)(encoded.data.as_slice()))
+    }
+}
+
+/// The error returned when a replay fails.
+#[derive(Clone, PartialEq, Show)]
+pub enum ReplayError {
+    /// A recorded command couldn't be decoded.
+    UnknownCommand { frame: usize, name: String },
+    /// A command run during the frame couldn't be encoded when recording.
+    UnrecordableCommand { frame: usize },
+    /// The world hash differs from the recorded one at the end of a frame.
+    Divergence { frame: usize, expected: u64, found: u64 }
+}
+
+/// A recorded frame.
+struct Frame {
+    start: Entry,
+    commands: Vec<Option<Encoded>>,
+    hash: Option<u64>
+}
+
+/// Replays a recorded session.
+pub struct Replay {
+    frames: Vec<Frame>
+}
+
+impl Replay {
+    /// Creates a replay from recorded entries.
+    ///
+    /// The entries recorded before the first frame are ignored.
+    pub fn new(entries: Vec<Entry>) -> Replay {
+        let mut frames: Vec<Frame> = Vec::new();
+        for entry in entries.into_iter() {
+            match entry {
+                Entry::Update(_) | Entry::FixedUpdate => frames.push(Frame {
+                    start: entry,
+                    commands: Vec::new(),
+                    hash: None
+                }),
+                Entry::Command(Stream::Space, encoded) => {
+                    if let Some(frame) = frames.last_mut() {
+                        frame.commands.push(encoded);
+                    }
+                },
+                Entry::Command(Stream::System, _) => {},
+                Entry::Hash(hash) => {
+                    if let Some(frame) = frames.last_mut() {
+                        frame.hash = Some(hash);
+                    }
+                }
+            }
+        }
+
+        Replay {
+            frames: frames
+        }
+    }
+
+    /// Loads a replay from a file saved by a `Recorder`.
+    pub fn load(path: &Path) -> IoResult<Replay> {
+        let mut reader = BufferedReader::new(try!(File::open(path)));
+        let mut entries = Vec::new();
+        for line in reader.lines() {
+            let line = try!(line);
+            let line = if line.ends_with("\n") { &line[..line.len() - 1] } else { &line[] };
+            match Entry::parse(line) {
+                Some(entry) => entries.push(entry),
+                None => return Err(IoError {
+                    kind: IoErrorKind::InvalidInput,
+                    desc: "invalid replay entry",
+                    detail: Some(line.to_string())
+                })
+            }
+        }
+
+        Ok(Replay::new(entries))
+    }
+
+    /// Returns the number of recorded frames.
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Replays the session on a space, which must be set up like the recorded one.
+    ///
+    /// Stops at the first frame whose world hash differs from the recorded one,
+    /// or which ran a command that couldn't be recorded.
+    pub fn run(&self, space: &mut Space, decoders: &Decoders<SpaceCommand>)
+               -> Result<(), ReplayError> {
+        let mut sender = space.command_sender();
+        for (i, frame) in self.frames.iter().enumerate() {
+            for encoded in frame.commands.iter() {
+                let encoded = match *encoded {
+                    Some(ref encoded) => encoded,
+                    None => return Err(ReplayError::UnrecordableCommand { frame: i })
+                };
+                match decoders.decode(encoded) {
+                    Some(command) => sender.send(command).unwrap(),
+                    None => return Err(ReplayError::UnknownCommand {
+                        frame: i,
+                        name: encoded.name.clone()
+                    })
+                }
+            }
+
+            match frame.start {
+                Entry::Update(dt) => space.update(dt),
+                _ => space.fixed_update()
+            }
+
+            if let Some(expected) = frame.hash {
+                let found = space.world_hash();
+                if found != expected {
+                    return Err(ReplayError::Divergence {
+                        frame: i,
+                        expected: expected,
+                        found: found
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use command::{Command, Encoded};
+    use space::{Space, SpaceCommand};
+    use super::{Entry, Stream, Recorder, Replay, ReplayError, Decoders};
+
+    /// Creates an entity.
+    struct Spawn;
+
+    impl<'a> Command for Spawn {
+        type Args = &'a mut Space;
+        fn run(&mut self, space: &'a mut Space) {
+            space.em.create_entity();
+        }
+        fn encode(&self) -> Option<Encoded> {
+            Some(Encoded::new("spawn", String::new()))
+        }
+    }
+
+    /// Creates an entity and sends a `Spawn` for the next frame.
+    struct SpawnTwice;
+
+    impl<'a> Command for SpawnTwice {
+        type Args = &'a mut Space;
+        fn run(&mut self, space: &'a mut Space) {
+            space.em.create_entity();
+            space.command_sender().send(Box::new(Spawn)).ok().unwrap();
+        }
+        fn encode(&self) -> Option<Encoded> {
+            Some(Encoded::new("spawn_twice", String::new()))
+        }
+    }
+
+    fn decode_spawn(_data: &str) -> Option<SpaceCommand> {
+        Some(Box::new(Spawn))
+    }
+
+    fn decode_spawn_twice(_data: &str) -> Option<SpaceCommand> {
+        Some(Box::new(SpawnTwice))
+    }
+
+    fn decoders() -> Decoders<SpaceCommand> {
+        let mut decoders = Decoders::new();
+        decoders.register("spawn", decode_spawn);
+        decoders.register("spawn_twice", decode_spawn_twice);
+        decoders
+    }
+
+    #[test]
+    fn entry_round_trip() {
+        let entries = vec![
+            Entry::Update(1. / 60.),
+            Entry::FixedUpdate,
+            Entry::Command(Stream::Space, Some(Encoded::new("move", "1 2.5".to_string()))),
+            Entry::Command(Stream::Space, Some(Encoded::new("spawn", String::new()))),
+            Entry::Command(Stream::Space, Some(Encoded::new("say", "a\nb \\n\r".to_string()))),
+            Entry::Command(Stream::System, None),
+            Entry::Hash(18446744073709551615)
+        ];
+
+        for entry in entries.into_iter() {
+            let line = entry.format();
+            assert!(!line.contains_char('\n'));
+            assert_eq!(Entry::parse(line.as_slice()), Some(entry));
+        }
+        assert!(Entry::parse("unknown").is_none());
+        assert!(Entry::parse("update abc").is_none());
+        assert!(Entry::parse("space say a\\b").is_none());
+    }
+
+    #[test]
+    #[should_fail]
+    fn encoded_name_with_space() {
+        Encoded::new("two words", String::new());
+    }
+
+    #[test]
+    fn replay_frames() {
+        let replay = Replay::new(vec![
+            Entry::Command(Stream::Space, None),
+            Entry::Update(0.5),
+            Entry::Command(Stream::Space, Some(Encoded::new("spawn", String::new()))),
+            Entry::Command(Stream::System, None),
+            Entry::Hash(1),
+            Entry::FixedUpdate,
+            Entry::Command(Stream::Space, None)
+        ]);
+
+        assert_eq!(replay.frame_count(), 2);
+        assert_eq!(replay.frames[0].commands.len(), 1);
+        assert_eq!(replay.frames[0].hash, Some(1));
+        assert_eq!(replay.frames[1].commands, vec![None]);
+        assert!(replay.frames[1].hash.is_none());
+    }
+
+    #[test]
+    fn replay() {
+        let (mut space, mut sender) = Space::new();
+        let recorder = Recorder::new();
+        space.record(recorder.clone());
+
+        sender.send(Box::new(SpawnTwice)).ok().unwrap();
+        space.update(0.5);
+        space.fixed_update();
+        sender.send(Box::new(Spawn)).ok().unwrap();
+        space.update(0.5);
+        assert_eq!(space.em.entities().len(), 3);
+
+        // The `Spawn` sent by `SpawnTwice` isn't recorded.
+        let commands = recorder.entries().into_iter().filter(|entry| match *entry {
+            Entry::Command(..) => true,
+            _ => false
+        }).count();
+        assert_eq!(commands, 2);
+
+        let (mut replayed, _) = Space::new();
+        assert_eq!(Replay::new(recorder.entries()).run(&mut replayed, &decoders()), Ok(()));
+        assert_eq!(replayed.em.entities().len(), 3);
+        assert_eq!(replayed.world_hash(), space.world_hash());
+    }
+
+    #[test]
+    fn record_sync_in_frame() {
+        let (mut space, mut sender, sync_sender) = Space::new_sync();
+        let recorder = Recorder::new();
+        space.record(recorder.clone());
+
+        let mut sync_sender = sync_sender;
+        sender.send_fn(move |_: &mut Space| {
+            sync_sender.send(Box::new(Spawn)).ok().unwrap();
+        }).ok().unwrap();
+        space.update(0.5);
+        space.update(0.5);
+        assert_eq!(space.em.entities().len(), 1);
+
+        // Only the closure is recorded, the `Spawn` being sent during a frame.
+        let commands: Vec<Entry> = recorder.entries().into_iter().filter(|entry| match *entry {
+            Entry::Command(..) => true,
+            _ => false
+        }).collect();
+        assert_eq!(commands, vec![Entry::Command(Stream::Space, None)]);
+    }
+
+    #[test]
+    fn replay_divergence() {
+        let (mut space, mut sender) = Space::new();
+        let recorder = Recorder::new();
+        space.record(recorder.clone());
+        sender.send(Box::new(Spawn)).ok().unwrap();
+        space.update(0.5);
+
+        let (mut replayed, _) = Space::new();
+        replayed.em.create_entity();
+        match Replay::new(recorder.entries()).run(&mut replayed, &decoders()) {
+            Err(ReplayError::Divergence { frame: 0, .. }) => {},
+            result => panic!("Unexpected replay result {:?}", result)
+        }
+    }
+
+    #[test]
+    fn replay_unrecordable() {
+        let (mut space, mut sender) = Space::new();
+        let recorder = Recorder::new();
+        space.record(recorder.clone());
+        space.update(0.5);
+        sender.send_fn(|space: &mut Space| { space.em.create_entity(); }).ok().unwrap();
+        space.update(0.5);
+
+        let (mut replayed, _) = Space::new();
+        assert_eq!(Replay::new(recorder.entries()).run(&mut replayed, &decoders()),
+                   Err(ReplayError::UnrecordableCommand { frame: 1 }));
+    }
+
+    #[test]
+    fn replay_unknown() {
+        let (mut space, mut sender) = Space::new();
+        let recorder = Recorder::new();
+        space.record(recorder.clone());
+        sender.send(Box::new(Spawn)).ok().unwrap();
+        space.update(0.5);
+
+        let (mut replayed, _) = Space::new();
+        assert_eq!(Replay::new(recorder.entries()).run(&mut replayed, &Decoders::new()),
+                   Err(ReplayError::UnknownCommand { frame: 0, name: "spawn".to_string() }));
+    }
+}
@@ -1,54 +1,591 @@
 //! Convenient object of the library.
 
-use command::{self, CommandReceiver, CommandSender, Command};
-use component::ComponentMapper;
-use entity::EntityMapper;
+use std::hash::{Hash, Hasher, SipHasher};
+
+use command::{self, CommandSender, SyncCommandReceiver, SyncCommandSender, SharedBuffer,
+              Command, FromFn, Encoded, Channels, SendError, Scheduled};
+use component::{self, Component, ComponentMapper};
+use entity::{Entity, EntityMapper, EntityReserver, Reservation, DEFAULT_NAMESPACE};
+use event::EventBus;
+use record::{Recorder, Entry, Stream};
 use system::SystemMapper;
+use timestep::{Timestep, Interpolation, DEFAULT_STEP, DEFAULT_MAX_STEPS};
+
+pub type SpaceCommand = Box<for<'a> Command<Args = &'a mut Space>>;
+pub type SyncSpaceCommand = Box<for<'a> Command<Args = &'a mut Space> + Send>;
+
+/// A closure run as a `SpaceCommand`.
+pub struct SpaceFn<F>(pub F);
+
+impl<'a, F> Command for SpaceFn<F>
+    where F: FnMut(&mut Space) + 'static
+{
+    type Args = &'a mut Space;
+    fn run(&mut self, space: &'a mut Space) {
+        (self.0)(space)
+    }
+}
+
+impl<F> FromFn<F> for SpaceCommand
+    where F: FnMut(&mut Space) + 'static
+{
+    fn from_fn(func: F) -> SpaceCommand {
+        Box::new(SpaceFn(func))
+    }
+}
+
+impl<F> FromFn<F> for SyncSpaceCommand
+    where F: FnMut(&mut Space) + Send + 'static
+{
+    fn from_fn(func: F) -> SyncSpaceCommand {
+        Box::new(SpaceFn(func))
+    }
+}
+
+/// A command creating an entity whose identifier was reserved.
+struct CreateEntity(Option<Reservation>);
+
+impl<'a> Command for CreateEntity {
+    type Args = &'a mut Space;
+    fn run(&mut self, space: &'a mut Space) {
+        if let Some(reservation) = self.0.take() {
+            space.em.create_reserved(reservation);
+        }
+    }
+}
+
+/// Converts a reservation into a command creating the entity.
+///
+/// This is what allows `send_create_entity` on the senders of the space streams,
+/// thread safe or not.
+pub trait FromReservation {
+    /// Wraps the reservation into a command creating the entity.
+    fn from_reservation(reservation: Reservation) -> Self;
+}
+
+impl FromReservation for SpaceCommand {
+    fn from_reservation(reservation: Reservation) -> SpaceCommand {
+        Box::new(CreateEntity(Some(reservation)))
+    }
+}
+
+impl FromReservation for SyncSpaceCommand {
+    fn from_reservation(reservation: Reservation) -> SyncSpaceCommand {
+        Box::new(CreateEntity(Some(reservation)))
+    }
+}
+
+impl<C, B> CommandSender<C, B>
+    where C: FromReservation, B: SharedBuffer<C>
+{
+    /// Reserves an entity identifier and sends a command creating the entity.
+    ///
+    /// The entity can be used right away, for instance in the next commands,
+    /// but it only exists once the command is run.
+    /// If the command can't be sent, the identifier is reclaimed.
+    pub fn send_create_entity(&mut self, reserver: &EntityReserver)
+        -> Result<Entity, SendError<C>>
+    {
+        let reservation = reserver.reserve();
+        let entity = reservation.entity();
+        self.send(FromReservation::from_reservation(reservation)).map(|_| entity)
+    }
+}
+
+/// A command taken from a channel by `Space::run_commands`.
+enum Taken {
+    /// A pending command, flagged if it was sent during a frame.
+    Pending(SpaceCommand, bool),
+    /// A due scheduled command, with the name of its channel.
+    Due(String, Scheduled<SpaceCommand>)
+}
+
+/// Hashes the component of an entity, if any, for the world hash.
+type ComponentHasher = fn(&ComponentMapper, Entity, &mut SipHasher);
 
-pub type SpaceCommand = Box<for<'a> Command<Args = &'a Space>>;
+fn hash_component<C>(cm: &ComponentMapper, entity: Entity, state: &mut SipHasher)
+    where C: Component + Hash<SipHasher>
+{
+    if let Some(component) = cm.try_get::<C>(entity) {
+        component::index_of::<C>().hash(state);
+        component.hash(state);
+    }
+}
 
-/// Regroups the three essential mappers and the blackboard.
+/// Regroups the three essential mappers and the event bus.
 ///
-/// It's also responsible of updates and command execution.
+/// It's also responsible of updates and command execution,
+/// and of running fixed updates according to its `timestep`.
 pub struct Space {
-    cmd_receiver: CommandReceiver<SpaceCommand>,
+    channels: Channels<SpaceCommand>,
+    sync_cmd_receiver: Option<SyncCommandReceiver<SyncSpaceCommand>>,
+    recorder: Option<Recorder>,
+    hashers: Vec<ComponentHasher>,
     pub em: EntityMapper,
     pub cm: ComponentMapper,
-    pub sm: SystemMapper
+    pub sm: SystemMapper,
+    pub events: EventBus,
+    pub timestep: Timestep
 }
 
 impl Space {
-    /// Creates a new `Space` and a `CommandSender`
+    /// Creates a new `Space` and a `CommandSender` to its default command channel.
     pub fn new() -> (Space, CommandSender<SpaceCommand>) {
-        let (sender, receiver) = command::stream();
+        let channels = Channels::new();
+        let sender = channels.default_sender();
+        let em = EntityMapper::new();
+        let mut cm = ComponentMapper::new();
+        cm.use_debug_names(em.debug_names());
+        let events = EventBus::new();
+        let mut sm = SystemMapper::with_event_bus(events.clone());
+        sm.use_group_names(em.group_names());
 
         (Space {
-            cmd_receiver: receiver,
-            em: EntityMapper::new(),
-            cm: ComponentMapper::new(),
-            sm: SystemMapper::new()
+            channels: channels,
+            sync_cmd_receiver: None,
+            recorder: None,
+            hashers: Vec::new(),
+            em: em,
+            cm: cm,
+            sm: sm,
+            events: events,
+            timestep: Timestep::new(DEFAULT_STEP, DEFAULT_MAX_STEPS)
         },
         sender)
     }
 
-    /// Runs pending commands and updates systems according to the
-    /// given delta time. This should be called every frame.
+    /// Creates a new `Space`, a `CommandSender` to its default command channel
+    /// and a `SyncCommandSender` which can be sent to other threads, such as asset loaders.
+    pub fn new_sync() -> (Space, CommandSender<SpaceCommand>, SyncCommandSender<SyncSpaceCommand>) {
+        let (sync_sender, receiver) = command::sync_stream();
+        let (mut space, sender) = Space::new();
+        space.sync_cmd_receiver = Some(receiver);
+
+        (space, sender, sync_sender)
+    }
+
+    /// Returns a new sender to the default command channel.
+    pub fn command_sender(&self) -> CommandSender<SpaceCommand> {
+        self.channels.default_sender()
+    }
+
+    /// Adds a command channel, processed after the existing ones, and returns its sender.
+    ///
+    /// If the channel already exists, its sender is returned.
+    /// See the [channel](../command/channel/index.html) module.
+    pub fn add_command_channel(&mut self, name: &str) -> CommandSender<SpaceCommand> {
+        self.channels.add(name)
+    }
+
+    /// Returns a sender to a command channel if it exists.
+    pub fn command_channel(&self, name: &str) -> Option<CommandSender<SpaceCommand>> {
+        self.channels.sender(name)
+    }
+
+    /// Sets the processing order of the command channels.
+    ///
+    /// The given channels come first, in the given order,
+    /// the others keeping their relative order after them.
+    /// When called from a command, the new order applies from the next frame.
+    /// Panics if a given channel doesn't exist.
+    pub fn set_command_channel_order(&mut self, names: &[&str]) {
+        self.channels.set_order(names);
+    }
+
+    /// Records the frames, the commands run and the world hashes with the given recorder.
+    ///
+    /// See the [record](../record/index.html) module.
+    pub fn record(&mut self, recorder: Recorder) {
+        self.sm.record(recorder.clone());
+        self.recorder = Some(recorder);
+    }
+
+    /// Stops recording.
+    pub fn stop_recording(&mut self) {
+        self.sm.stop_recording();
+        self.recorder = None;
+    }
+
+    /// Includes the components of type `C` in the world hash.
+    pub fn hash_components<C>(&mut self)
+        where C: Component + Hash<SipHasher>
+    {
+        self.hashers.push(hash_component::<C> as ComponentHasher);
+    }
+
+    /// Returns a hash of the world, used to check that a replay doesn't diverge.
+    ///
+    /// It covers the entities with their awake state, tags, groups and component types,
+    /// as well as the components of the types given to `hash_components`.
+    pub fn world_hash(&self) -> u64 {
+        let mut state = SipHasher::new();
+        for entity in self.em.entities().into_iter() {
+            let mentity = self.em.get_mentity(entity);
+            entity.hash(&mut state);
+            mentity.is_awake.hash(&mut state);
+
+            let mut tags: Vec<(String, String)> = self.em.tags_of(entity).into_iter()
+                                                      .map(|tag| (tag.namespace, tag.name))
+                                                      .collect();
+            tags.sort();
+            tags.hash(&mut state);
+
+            let mut groups = self.em.groups_of(entity);
+            groups.sort();
+            groups.hash(&mut state);
+
+            for type_index in mentity.components.iter() {
+                type_index.hash(&mut state);
+            }
+            for hasher in self.hashers.iter() {
+                (*hasher)(&self.cm, entity, &mut state);
+            }
+        }
+        state.finish()
+    }
+
+    /// Returns a human-readable description of an entity, for debugging purposes.
+    ///
+    /// It contains the entity debug name, awake state, tags, groups
+    /// and the names of its component types.
+    pub fn describe(&self, entity: Entity) -> String {
+        let mentity = self.em.get_mentity(entity);
+
+        let mut tags: Vec<String> = self.em.tags_of(entity).into_iter().map(|tag| {
+            if tag.namespace.as_slice() == DEFAULT_NAMESPACE {
+                tag.name
+            } else {
+                format!("{}:{}", tag.namespace, tag.name)
+            }
+        }).collect();
+        tags.sort();
+
+        let mut groups = self.em.groups_of(entity);
+        groups.sort();
+
+        let components = self.cm.component_names(&mentity.components);
+
+        format!("entity {}\n  awake: {}\n  tags: {}\n  groups: {}\n  components: {}",
+                self.em.label(entity), mentity.is_awake,
+                tags.connect(", "), groups.connect(", "), components.connect(", "))
+    }
+
+    /// Returns a handle to the interpolation alpha between two fixed updates,
+    /// updated on each tick.
+    pub fn interpolation(&self) -> Interpolation {
+        self.timestep.interpolation()
+    }
+
+    /// Runs the fixed updates due according to the real delta time, then a regular update.
+    ///
+    /// The number of fixed updates is capped, see the [timestep](../timestep/index.html) module.
+    /// This should be called every frame, instead of `update` and `fixed_update`.
+    pub fn tick(&mut self, real_dt: f32) {
+        let steps = self.timestep.advance(real_dt);
+        for _ in range(0, steps) {
+            self.fixed_update();
+        }
+        self.update(real_dt);
+    }
+
+    /// Runs pending and due scheduled commands, removes the entities whose lifetime
+    /// in seconds expired and updates systems according to the given delta time.
+    /// The event buffers are then swapped.
+    /// This should be called every frame.
     pub fn update(&mut self, dt: f32) {
+        self.record_entry(Entry::Update(dt));
+        self.set_in_frame(true);
+        for receiver in self.channels.receivers_mut().into_iter() {
+            receiver.advance_time(dt);
+        }
+        if let Some(ref mut receiver) = self.sync_cmd_receiver {
+            receiver.advance_time(dt);
+        }
         self.run_commands();
+        self.em.update_lifetimes(&mut self.cm, dt);
         self.sm.update(&mut self.em, &mut self.cm, dt);
+        self.set_in_frame(false);
+        self.record_hash();
     }
 
-    /// Runs pending commands and updates systems. This should be called
-    /// at a fixed timestep.
+    /// Runs pending and due scheduled commands, removes the entities whose lifetime
+    /// in ticks expired and updates systems. This should be called at a fixed timestep.
     pub fn fixed_update(&mut self) {
+        self.record_entry(Entry::FixedUpdate);
+        self.set_in_frame(true);
+        for receiver in self.channels.receivers_mut().into_iter() {
+            receiver.advance_tick();
+        }
+        if let Some(ref mut receiver) = self.sync_cmd_receiver {
+            receiver.advance_tick();
+        }
         self.run_commands();
+        self.em.fixed_update_lifetimes(&mut self.cm);
         self.sm.fixed_update(&mut self.em, &mut self.cm);
+        self.set_in_frame(false);
+        self.record_hash();
     }
 
-    /// Runs pending commands.
+    /// Runs the commands pending at the start of the call.
+    ///
+    /// The pending and due scheduled commands are taken from the channels before running any,
+    /// so that the commands they send, whatever their priority, are run on the next call,
+    /// and that a command sending itself again can't loop forever.
+    /// The channels are processed in order, the due scheduled commands of a channel
+    /// being run after its pending ones, and the commands from other threads are run last.
+    ///
+    /// Only the commands sent from outside of the frames are recorded,
+    /// the others being sent again by the replayed commands and systems.
+    /// This holds for the commands from other threads, which should thus be sent
+    /// between frames while recording.
     fn run_commands(&mut self) {
-        while let Some(mut command) = self.cmd_receiver.recv() {
-            command.run(self)
+        let sync_pending = self.sync_cmd_receiver.as_ref().map_or(0, |receiver| receiver.len());
+        let mut taken = Vec::new();
+        for (name, receiver) in self.channels.named_receivers_mut().into_iter() {
+            while let Some((command, in_frame)) = receiver.recv_flagged() {
+                taken.push(Taken::Pending(command, in_frame));
+            }
+            while let Some(scheduled) = receiver.recv_due() {
+                taken.push(Taken::Due(name.to_string(), scheduled));
+            }
+        }
+
+        for entry in taken.into_iter() {
+            match entry {
+                Taken::Pending(mut command, in_frame) => {
+                    if !in_frame {
+                        self.record_command(|| command.encode());
+                    }
+                    command.run(self);
+                },
+                Taken::Due(channel, mut scheduled) => {
+                    // Cancelled by a command run before it.
+                    if scheduled.is_cancelled() {
+                        continue;
+                    }
+                    if !scheduled.is_in_frame() {
+                        self.record_command(|| scheduled.command.encode());
+                    }
+                    scheduled.command.run(self);
+                    if let Some(receiver) = self.channels.receiver_mut(channel.as_slice()) {
+                        receiver.reschedule(scheduled);
+                    }
+                }
+            }
+        }
+
+        for _ in range(0, sync_pending) {
+            match self.sync_cmd_receiver.as_mut().and_then(|receiver| receiver.recv_flagged()) {
+                Some((mut command, in_frame)) => {
+                    if !in_frame {
+                        self.record_command(|| command.encode());
+                    }
+                    command.run(self);
+                },
+                None => break
+            }
+        }
+
+        while let Some(mut scheduled) = self.sync_cmd_receiver.as_mut()
+                                            .and_then(|receiver| receiver.recv_due()) {
+            if !scheduled.is_in_frame() {
+                self.record_command(|| scheduled.command.encode());
+            }
+            scheduled.command.run(self);
+            if let Some(ref mut receiver) = self.sync_cmd_receiver {
+                receiver.reschedule(scheduled);
+            }
+        }
+    }
+
+    /// Flags the commands sent from now on to the channels and from other threads
+    /// as sent during a frame or not.
+    fn set_in_frame(&mut self, in_frame: bool) {
+        for receiver in self.channels.receivers_mut().into_iter() {
+            receiver.set_in_frame(in_frame);
+        }
+        if let Some(ref mut receiver) = self.sync_cmd_receiver {
+            receiver.set_in_frame(in_frame);
+        }
+    }
+
+    /// Records an entry if recording.
+    fn record_entry(&self, entry: Entry) {
+        if let Some(ref recorder) = self.recorder {
+            recorder.record(entry);
+        }
+    }
+
+    /// Records a space command if recording, encoding it only then.
+    fn record_command<F>(&self, encode: F)
+        where F: FnOnce() -> Option<Encoded>
+    {
+        if let Some(ref recorder) = self.recorder {
+            recorder.record(Entry::Command(Stream::Space, encode()));
+        }
+    }
+
+    /// Records the world hash if recording.
+    fn record_hash(&self) {
+        if self.recorder.is_some() {
+            self.record_entry(Entry::Hash(self.world_hash()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use std::cell::{Cell, RefCell};
+    use std::thread::Thread;
+
+    use command::{Command, FromFn, Priority};
+    use super::Space;
+
+    /// Creates an entity and sends itself again.
+    struct Echo {
+        runs: Rc<Cell<usize>>
+    }
+
+    impl<'a> Command for Echo {
+        type Args = &'a mut Space;
+        fn run(&mut self, space: &'a mut Space) {
+            self.runs.set(self.runs.get() + 1);
+            space.em.create_entity();
+            let echo = Echo { runs: self.runs.clone() };
+            space.command_sender().send(Box::new(echo)).ok().unwrap();
         }
     }
+
+    #[test]
+    fn command_sending_itself() {
+        let (mut space, mut sender) = Space::new();
+        let runs = Rc::new(Cell::new(0));
+        sender.send(Box::new(Echo { runs: runs.clone() })).ok().unwrap();
+
+        space.update(0.);
+        assert_eq!(runs.get(), 1);
+        space.update(0.);
+        space.fixed_update();
+        assert_eq!(runs.get(), 3);
+        assert_eq!(space.em.entities().len(), 3);
+    }
+
+    #[test]
+    fn sync_commands() {
+        let (mut space, mut sender, sync_sender) = Space::new_sync();
+        let reserver = space.em.reserver();
+
+        let loaded = Thread::scoped(move || {
+            let mut sync_sender = sync_sender;
+            let entity = sync_sender.send_create_entity(&reserver).ok().unwrap();
+            sync_sender.send_fn(move |space: &mut Space| {
+                space.em.set_tag(entity, "loaded").ok().unwrap();
+            }).ok().unwrap();
+            entity
+        }).join().ok().unwrap();
+
+        sender.send_fn(|space: &mut Space| { space.em.create_entity(); }).ok().unwrap();
+        space.update(0.);
+
+        assert_eq!(space.em.entities().len(), 2);
+        assert_eq!(space.em.try_get_tag("loaded"), Some(loaded));
+    }
+
+    #[test]
+    fn commands_sent_while_running() {
+        let (mut space, mut sender) = Space::new();
+        let order = Rc::new(RefCell::new(Vec::new()));
+
+        let log = order.clone();
+        sender.send_fn(move |space: &mut Space| {
+            log.borrow_mut().push(0);
+            let log = log.clone();
+            space.command_sender().send_with_priority(Priority::High, FromFn::from_fn(
+                move |_: &mut Space| log.borrow_mut().push(2)
+            )).ok().unwrap();
+        }).ok().unwrap();
+        let log = order.clone();
+        sender.send_fn(move |_: &mut Space| log.borrow_mut().push(1)).ok().unwrap();
+
+        // The high priority command doesn't take the place of a pending one.
+        space.update(0.);
+        assert_eq!(*order.borrow(), vec![0, 1]);
+        space.update(0.);
+        assert_eq!(*order.borrow(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn channel_order_changed_while_running() {
+        let (mut space, mut sender) = Space::new();
+        let mut late = space.add_command_channel("late");
+        let order = Rc::new(RefCell::new(Vec::new()));
+
+        let log = order.clone();
+        sender.send_fn(move |space: &mut Space| {
+            log.borrow_mut().push(0);
+            space.set_command_channel_order(&["late"]);
+        }).ok().unwrap();
+        let log = order.clone();
+        late.send_fn(move |_: &mut Space| log.borrow_mut().push(1)).ok().unwrap();
+        let log = order.clone();
+        sender.send_fn(move |_: &mut Space| log.borrow_mut().push(2)).ok().unwrap();
+
+        space.update(0.);
+        assert_eq!(*order.borrow(), vec![0, 2, 1]);
+
+        let log = order.clone();
+        sender.send_fn(move |_: &mut Space| log.borrow_mut().push(3)).ok().unwrap();
+        let log = order.clone();
+        late.send_fn(move |_: &mut Space| log.borrow_mut().push(4)).ok().unwrap();
+        space.update(0.);
+        assert_eq!(*order.borrow(), vec![0, 2, 1, 4, 3]);
+    }
+
+    #[test]
+    fn reply() {
+        let (mut space, mut sender) = Space::new();
+        let reply = sender.send_with_reply(|replier| FromFn::from_fn(move |space: &mut Space| {
+            replier.send(space.em.create_entity());
+        })).ok().unwrap();
+
+        assert!(!reply.is_ready());
+        space.update(0.);
+        assert!(reply.is_ready());
+        assert_eq!(reply.try_take(), Some(0));
+        assert!(reply.try_take().is_none());
+    }
+
+    #[test]
+    fn reply_disconnected() {
+        let (space, mut sender) = Space::new();
+        let reply = sender.send_with_reply(|replier| FromFn::from_fn(move |space: &mut Space| {
+            replier.send(space.em.create_entity());
+        })).ok().unwrap();
+
+        // The pending command is dropped along with the receiver, without running.
+        drop(space);
+        assert!(!reply.is_ready());
+        assert!(reply.try_take().is_none());
+    }
+
+    #[test]
+    fn send_create_entity() {
+        let (mut space, mut sender) = Space::new();
+        let reserver = space.em.reserver();
+
+        let entity = sender.send_create_entity(&reserver).ok().unwrap();
+        assert!(space.em.entities().is_empty());
+        space.update(0.);
+        assert_eq!(space.em.entities(), vec![entity]);
+    }
+
+    #[test]
+    fn send_create_entity_disconnected() {
+        let (space, mut sender) = Space::new();
+        let reserver = space.em.reserver();
+        drop(space);
+
+        assert!(sender.send_create_entity(&reserver).is_err());
+    }
 }
@@ -0,0 +1,317 @@
+//! Structural changes deferred until a system finishes its update.
+//!
+//! Removing entities while iterating an `EntityView` is awkward,
+//! so a system records its structural changes in the `EntityCommands` it is given,
+//! the `SystemMapper` applying them right after the system update:
+//!
+//! ````ignore
+//! fn update(&mut self, _em: &mut EntityMapper, cm: &mut ComponentMapper,
+//!           commands: &mut EntityCommands, _dt: f32) {
+//!     for entity in self.view.iter() {
+//!         if cm.get::<Health>(entity).points <= 0 {
+//!             let corpse = commands.spawn();
+//!             commands.insert(corpse, Corpse::new());
+//!             commands.remove(entity);
+//!         }
+//!     }
+//! }
+//! ````
+//!
+//! The changes are applied in the order they were recorded,
+//! and the systems are notified of them before the next system update.
+//! A deferred tag change failing on a conflict is skipped, the `SystemMapper`
+//! sending the `TagConflict` on its event bus.
+
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use entity::{Entity, EntityMapper, EntityReserver, TagPolicy, TagConflict};
+use component::{Component, ComponentMapper, EntityReferrer};
+use command::{Command, FromFn};
+use system::InterSystemCommand;
+
+/// Records structural changes to apply later on.
+pub struct EntityCommands {
+    reserver: EntityReserver,
+    commands: Vec<InterSystemCommand>,
+    conflicts: Rc<RefCell<Vec<TagConflict>>>
+}
+
+impl EntityCommands {
+    /// Creates an empty `EntityCommands` for the entities of the given mapper.
+    pub fn new(em: &EntityMapper) -> EntityCommands {
+        EntityCommands {
+            reserver: em.reserver(),
+            commands: Vec::new(),
+            conflicts: Rc::new(RefCell::new(Vec::new()))
+        }
+    }
+
+    /// Creates an entity.
+    ///
+    /// Its identifier is reserved right away, so that it can be used by the next changes,
+    /// but the entity only exists once the changes are applied.
+    pub fn spawn(&mut self) -> Entity {
+        let mut reservation = Some(self.reserver.reserve());
+        let entity = reservation.as_ref().unwrap().entity();
+        self.push(move |em, _| { em.create_reserved(reservation.take().unwrap()); });
+        entity
+    }
+
+    /// Removes an entity.
+    pub fn remove(&mut self, entity: Entity) {
+        self.push(move |em, _| em.remove_entity(entity));
+    }
+
+    /// Attaches a component to an entity.
+    pub fn insert<C>(&mut self, entity: Entity, component: C)
+        where C: Component
+    {
+        let mut component = Some(component);
+        self.push(move |em, cm| {
+            cm.insert(em.get_mentity_mut(entity), component.take().unwrap());
+        });
+    }
+
+    /// Attaches a component holding entity references to an entity.
+    pub fn insert_referrer<C>(&mut self, entity: Entity, component: C)
+        where C: EntityReferrer
+    {
+        let mut component = Some(component);
+        self.push(move |em, cm| {
+            cm.insert_referrer(em.get_mentity_mut(entity), component.take().unwrap());
+        });
+    }
+
+    /// Detaches a component from an entity.
+    pub fn remove_component<C>(&mut self, entity: Entity)
+        where C: Component
+    {
+        self.push(move |em, cm| cm.remove::<C>(em.get_mentity_mut(entity)));
+    }
+
+    /// Adds a tag to an entity, in the default namespace.
+    ///
+    /// If the tag was already used by another entity, the change is skipped
+    /// and the conflict is returned by `apply`.
+    pub fn set_tag(&mut self, entity: Entity, tag: &str) {
+        self.set_tag_with(entity, tag, TagPolicy::Fail);
+    }
+
+    /// Adds a tag to an entity, in the default namespace.
+    ///
+    /// If the tag was already used by another entity, the policy is applied.
+    /// A conflict of the `Fail` policy is returned by `apply`.
+    pub fn set_tag_with(&mut self, entity: Entity, tag: &str, policy: TagPolicy) {
+        let tag = tag.to_string();
+        let conflicts = self.conflicts.clone();
+        self.push(move |em, _| {
+            if let Err(conflict) = em.set_tag_with(entity, tag.as_slice(), policy) {
+                conflicts.borrow_mut().push(conflict);
+            }
+        });
+    }
+
+    /// Removes a tag of an entity, in the default namespace.
+    pub fn remove_tag(&mut self, entity: Entity, tag: &str) {
+        let tag = tag.to_string();
+        self.push(move |em, _| em.remove_tag(entity, tag.as_slice()));
+    }
+
+    /// Returns the number of recorded changes.
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    /// Returns `true` if no change is recorded.
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Applies the recorded changes in order, leaving the buffer empty.
+    ///
+    /// Returns the tag conflicts of the skipped tag changes.
+    pub fn apply(&mut self, em: &mut EntityMapper, cm: &mut ComponentMapper)
+        -> Vec<TagConflict>
+    {
+        for mut command in self.commands.drain() {
+            command.run((&mut *em, &mut *cm));
+        }
+        self.conflicts.borrow_mut().drain().collect()
+    }
+
+    /// Records a change.
+    fn push<F>(&mut self, func: F)
+        where F: FnMut(&mut EntityMapper, &mut ComponentMapper) + 'static
+    {
+        self.commands.push(FromFn::from_fn(func));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use std::cell::RefCell;
+
+    use entity::{MetaEntity, EntityMapper, Tag, TagPolicy, TagConflict};
+    use component::{Component, ComponentMapper};
+    use system::{System, SystemMapper, StandardEntityView, StandardEntityFilter, EntityView};
+    use event::EventBus;
+    use super::EntityCommands;
+
+    struct Health {
+        points: i32
+    }
+
+    impl Component for Health {
+        fn index_of() -> usize { 0 }
+    }
+
+    /// Removes the dead entities of its view, recording the view size on each update.
+    struct Reaper {
+        view: StandardEntityView,
+        sizes: Rc<RefCell<Vec<usize>>>
+    }
+
+    impl System for Reaper {
+        fn update(&mut self, _em: &mut EntityMapper, cm: &mut ComponentMapper,
+                  commands: &mut EntityCommands, _dt: f32) {
+            self.sizes.borrow_mut().push(self.view.len());
+            for &entity in self.view.iter() {
+                if cm.get::<Health>(entity).points <= 0 {
+                    commands.remove(entity);
+                }
+            }
+        }
+
+        fn on_entity_changed(&mut self, _cm: &ComponentMapper, mentity: &MetaEntity) {
+            self.view.update(mentity);
+        }
+
+        fn on_entity_removed(&mut self, _cm: &ComponentMapper, mentity: &MetaEntity) {
+            self.view.remove(mentity);
+        }
+    }
+
+    #[test]
+    fn spawn_then_insert() {
+        let mut em = EntityMapper::new();
+        let mut cm = ComponentMapper::new();
+        let mut commands = EntityCommands::new(&em);
+
+        let entity = commands.spawn();
+        commands.insert(entity, Health { points: 3 });
+        assert_eq!(commands.len(), 2);
+        assert!(em.entities().is_empty());
+
+        assert!(commands.apply(&mut em, &mut cm).is_empty());
+        assert!(commands.is_empty());
+        assert_eq!(em.entities(), vec![entity]);
+        assert_eq!(cm.get::<Health>(entity).points, 3);
+    }
+
+    #[test]
+    fn removal_during_view_iteration() {
+        let mut em = EntityMapper::new();
+        let mut cm = ComponentMapper::new();
+        let mut sm = SystemMapper::new();
+        let sizes = Rc::new(RefCell::new(Vec::new()));
+
+        let mut filter = StandardEntityFilter::new();
+        filter.require_component::<Health>();
+        sm.insert(Reaper { view: EntityView::new(filter), sizes: sizes.clone() });
+
+        let mut survivor = None;
+        for &points in [0, 2, -1].iter() {
+            let entity = em.create_entity();
+            cm.insert(em.get_mentity_mut(entity), Health { points: points });
+            if points > 0 {
+                survivor = Some(entity);
+            }
+        }
+
+        sm.update(&mut em, &mut cm, 0.);
+        sm.update(&mut em, &mut cm, 0.);
+        assert_eq!(*sizes.borrow(), vec![3, 1]);
+        assert_eq!(em.entities(), vec![survivor.unwrap()]);
+    }
+
+    #[test]
+    fn application_order() {
+        let mut em = EntityMapper::new();
+        let mut cm = ComponentMapper::new();
+        let mut commands = EntityCommands::new(&em);
+
+        let entity = commands.spawn();
+        commands.set_tag(entity, "first");
+        commands.remove_tag(entity, "first");
+        commands.set_tag(entity, "second");
+        commands.insert(entity, Health { points: 1 });
+        commands.remove_component::<Health>(entity);
+        assert!(commands.apply(&mut em, &mut cm).is_empty());
+
+        assert!(em.try_get_tag("first").is_none());
+        assert_eq!(em.try_get_tag("second"), Some(entity));
+        assert!(cm.try_get::<Health>(entity).is_none());
+    }
+
+    #[test]
+    fn tag_conflicts() {
+        let mut em = EntityMapper::new();
+        let mut cm = ComponentMapper::new();
+        let holder = em.create_entity();
+        let entity = em.create_entity();
+        em.set_tag(holder, "hero").unwrap();
+
+        let mut commands = EntityCommands::new(&em);
+        commands.set_tag(entity, "hero");
+        commands.set_tag(entity, "villain");
+        commands.set_tag_with(entity, "hero", TagPolicy::Fail);
+        let conflict = TagConflict { tag: Tag::new("hero"), holder: holder };
+
+        assert_eq!(commands.apply(&mut em, &mut cm), vec![conflict.clone(), conflict]);
+        assert_eq!(em.try_get_tag("hero"), Some(holder));
+        assert_eq!(em.try_get_tag("villain"), Some(entity));
+        assert!(commands.apply(&mut em, &mut cm).is_empty());
+    }
+
+    /// Tags every entity of its view with the same tag.
+    struct Tagger {
+        view: StandardEntityView
+    }
+
+    impl System for Tagger {
+        fn update(&mut self, _em: &mut EntityMapper, _cm: &mut ComponentMapper,
+                  commands: &mut EntityCommands, _dt: f32) {
+            for &entity in self.view.iter() {
+                commands.set_tag(entity, "hero");
+            }
+        }
+
+        fn on_entity_changed(&mut self, _cm: &ComponentMapper, mentity: &MetaEntity) {
+            self.view.update(mentity);
+        }
+
+        fn on_entity_removed(&mut self, _cm: &ComponentMapper, mentity: &MetaEntity) {
+            self.view.remove(mentity);
+        }
+    }
+
+    #[test]
+    fn tag_conflicts_sent_as_events() {
+        let mut em = EntityMapper::new();
+        let mut cm = ComponentMapper::new();
+        let events = EventBus::new();
+        let mut reader = events.reader();
+        let mut sm = SystemMapper::with_event_bus(events);
+        sm.insert(Tagger { view: EntityView::new(StandardEntityFilter::new()) });
+
+        em.create_entity();
+        em.create_entity();
+        sm.update(&mut em, &mut cm, 0.);
+
+        let holder = em.try_get_tag("hero").unwrap();
+        assert_eq!(reader.read::<TagConflict>(),
+                   vec![TagConflict { tag: Tag::new("hero"), holder: holder }]);
+    }
+}
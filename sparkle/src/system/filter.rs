@@ -1,4 +1,4 @@
-//! Filtering entities by groups and component types.
+//! Filtering entities by groups, relations and component types.
 //!
 //! ## Entity filters
 //!
@@ -12,6 +12,16 @@
 //! );
 //! ```
 //!
+//! Groups can be given as patterns using wildcards, such as `"enemies/*"`,
+//! see the [entity documentation](../../entity/index.html) for details.
+//!
+//! Relations can't be expressed with the macro, use `require_relation` and
+//! `forbid_relation` on the resulting `StandardEntityFilter` instead.
+//!
+//! Group names are interned by each `EntityMapper`, so a filter requiring
+//! or forbidding groups must be given its `GroupNames` before being used.
+//! The `SystemMapper` gives them to the systems, which give them to their views.
+//!
 //! ## Entity views
 //!
 //! An `EntityView` is useful to keep an eye on particular entities.  
@@ -41,6 +51,10 @@
 //!         }
 //!     }
 //!
+//!     fn use_group_names(&mut self, names: &GroupNames) {
+//!         self.view.use_group_names(names);
+//!     }
+//!
 //!     fn on_entity_changed(&mut self, _cm: &ComponentMapper, mentity: &MetaEntity) {
 //!         self.view.update(mentity);
 //!     }
@@ -52,9 +66,10 @@
 //! ````
 
 use std::ops::{Deref};
+use std::any::TypeId;
 use std::collections::{HashSet, BitvSet};
 
-use entity::{Entity, MetaEntity};
+use entity::{Entity, MetaEntity, Relation, GroupNames, GroupPattern};
 use component::{Component, index_of};
 
 /// The standard `EntityView`, just an alias.
@@ -79,8 +94,18 @@ impl<Filter> EntityView<Filter>
         }
     }
     
+    /// Gives the group names of the entities to the filter.
+    pub fn use_group_names(&mut self, names: &GroupNames) {
+        self.filter.use_group_names(names);
+    }
+
     /// Updates the view with the given entity.
+    ///
+    /// Sleeping entities are removed from the view,
+    /// use `System::on_entity_slept` to be notified of those.
     pub fn update(&mut self, mentity: &MetaEntity) {
+        self.filter.refresh();
+
         let contains = self.entities.contains(&mentity.entity);
         if contains && !mentity.is_awake {
             self.entities.remove(&mentity.entity);
@@ -115,14 +140,27 @@ impl<Filter> Deref for EntityView<Filter>
 pub trait EntityFilter {
     /// Determines if an entity passes the filter.
     fn pass(&self, mentity: &MetaEntity) -> bool;
+
+    /// Gives the group names of the entities to filter.
+    fn use_group_names(&mut self, _names: &GroupNames) {}
+
+    /// Takes the group names interned since the last call into account.
+    fn refresh(&mut self) {}
 }
 
 /// The provided implementation of an `EntityFilter`.
 pub struct StandardEntityFilter {
     mandatory_components: BitvSet,
     forbidden_components: BitvSet,
-    mandatory_groups: HashSet<String>,
-    forbidden_groups: HashSet<String>
+    mandatory_group_names: Vec<String>,
+    forbidden_group_names: Vec<String>,
+    mandatory_groups: BitvSet,
+    forbidden_groups: BitvSet,
+    mandatory_patterns: Vec<GroupPattern>,
+    forbidden_patterns: Vec<GroupPattern>,
+    mandatory_relations: HashSet<TypeId>,
+    forbidden_relations: HashSet<TypeId>,
+    names: Option<GroupNames>
 }
 
 impl StandardEntityFilter {
@@ -131,8 +169,15 @@ impl StandardEntityFilter {
         StandardEntityFilter {
             mandatory_components: BitvSet::new(),
             forbidden_components: BitvSet::new(),
-            mandatory_groups: HashSet::new(),
-            forbidden_groups: HashSet::new(),
+            mandatory_group_names: Vec::new(),
+            forbidden_group_names: Vec::new(),
+            mandatory_groups: BitvSet::new(),
+            forbidden_groups: BitvSet::new(),
+            mandatory_patterns: Vec::new(),
+            forbidden_patterns: Vec::new(),
+            mandatory_relations: HashSet::new(),
+            forbidden_relations: HashSet::new(),
+            names: None
         }
     }
 
@@ -153,21 +198,194 @@ impl StandardEntityFilter {
     }
 
     /// Adds a mandatory group.
+    ///
+    /// If the group name contains wildcards, entities must belong to at least one matching group.
     pub fn require_group(&mut self, group: &str) {
-        self.mandatory_groups.insert(group.to_string());
+        if GroupPattern::is_pattern(group) {
+            let mut pattern = GroupPattern::new(group);
+            if let Some(ref names) = self.names {
+                pattern.refresh(names);
+            }
+            self.mandatory_patterns.push(pattern);
+        } else {
+            if let Some(ref names) = self.names {
+                self.mandatory_groups.insert(names.intern(group).0);
+            }
+            self.mandatory_group_names.push(group.to_string());
+        }
     }
 
     /// Adds a forbidden group.
+    ///
+    /// If the group name contains wildcards, entities must not belong to any matching group.
     pub fn forbid_group(&mut self, group: &str) {
-        self.forbidden_groups.insert(group.to_string());
+        if GroupPattern::is_pattern(group) {
+            let mut pattern = GroupPattern::new(group);
+            if let Some(ref names) = self.names {
+                pattern.refresh(names);
+            }
+            self.forbidden_patterns.push(pattern);
+        } else {
+            if let Some(ref names) = self.names {
+                self.forbidden_groups.insert(names.intern(group).0);
+            }
+            self.forbidden_group_names.push(group.to_string());
+        }
+    }
+
+    /// Adds a mandatory relation type.
+    ///
+    /// Entities must be related to at least one entity by this type of relation.
+    pub fn require_relation<R>(&mut self)
+        where R: Relation
+    {
+        self.mandatory_relations.insert(TypeId::of::<R>());
+    }
+
+    /// Adds a forbidden relation type.
+    ///
+    /// Entities must not be related to any entity by this type of relation.
+    pub fn forbid_relation<R>(&mut self)
+        where R: Relation
+    {
+        self.forbidden_relations.insert(TypeId::of::<R>());
+    }
+
+    /// Returns `true` if the filter requires or forbids groups.
+    fn uses_groups(&self) -> bool {
+        !self.mandatory_group_names.is_empty() || !self.forbidden_group_names.is_empty() ||
+        !self.mandatory_patterns.is_empty() || !self.forbidden_patterns.is_empty()
     }
 }
 
 impl EntityFilter for StandardEntityFilter {
     fn pass(&self, mentity: &MetaEntity) -> bool {
+        if self.names.is_none() && self.uses_groups() {
+            panic!("The group names must be given to a filter using groups, \
+                    see EntityView::use_group_names");
+        }
+
         self.mandatory_components.is_subset(&mentity.components) &&
         self.forbidden_components.is_disjoint(&mentity.components) &&
         self.mandatory_groups.is_subset(&mentity.groups) &&
-        self.forbidden_groups.is_disjoint(&mentity.groups)
+        self.forbidden_groups.is_disjoint(&mentity.groups) &&
+        self.mandatory_patterns.iter().all(|pattern| pattern.matches_any(&mentity.groups)) &&
+        !self.forbidden_patterns.iter().any(|pattern| pattern.matches_any(&mentity.groups)) &&
+        self.mandatory_relations.is_subset(&mentity.relations) &&
+        self.forbidden_relations.is_disjoint(&mentity.relations)
+    }
+
+    fn use_group_names(&mut self, names: &GroupNames) {
+        let StandardEntityFilter {
+            ref mandatory_group_names, ref forbidden_group_names,
+            ref mut mandatory_groups, ref mut forbidden_groups,
+            ref mut mandatory_patterns, ref mut forbidden_patterns, ..
+        } = *self;
+
+        mandatory_groups.clear();
+        mandatory_groups.extend(mandatory_group_names.iter().map(|group| {
+            names.intern(group.as_slice()).0
+        }));
+        forbidden_groups.clear();
+        forbidden_groups.extend(forbidden_group_names.iter().map(|group| {
+            names.intern(group.as_slice()).0
+        }));
+        for pattern in mandatory_patterns.iter_mut().chain(forbidden_patterns.iter_mut()) {
+            pattern.reset();
+            pattern.refresh(names);
+        }
+
+        self.names = Some(names.clone());
+    }
+
+    fn refresh(&mut self) {
+        if let Some(ref names) = self.names {
+            for pattern in self.mandatory_patterns.iter_mut()
+                                                  .chain(self.forbidden_patterns.iter_mut()) {
+                pattern.refresh(names);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use entity::{EntityMapper, Relation};
+    use super::{EntityFilter, StandardEntityFilter};
+
+    struct Owns;
+    impl Relation for Owns {}
+
+    #[test]
+    fn groups() {
+        let mut em = EntityMapper::new();
+        let ally = em.create_entity();
+        let flying_ally = em.create_entity();
+        em.set_group(ally, "allies");
+
+        let mut filter = StandardEntityFilter::new();
+        filter.require_group("allies");
+        filter.forbid_group("enemies/*");
+        filter.use_group_names(&em.group_names());
+
+        em.set_group(flying_ally, "allies");
+        em.set_group(flying_ally, "enemies/flying");
+        filter.refresh();
+
+        assert!(filter.pass(em.get_mentity(ally)));
+        assert!(!filter.pass(em.get_mentity(flying_ally)));
+    }
+
+    #[test]
+    fn groups_of_another_mapper() {
+        let mut em = EntityMapper::new();
+        let mut other = EntityMapper::new();
+        let entity = em.create_entity();
+        let other_entity = other.create_entity();
+        em.set_group(entity, "visible");
+        other.set_group(other_entity, "hidden");
+        other.set_group(other_entity, "visible");
+
+        let mut filter = StandardEntityFilter::new();
+        filter.require_group("visible");
+        filter.use_group_names(&em.group_names());
+        assert!(filter.pass(em.get_mentity(entity)));
+
+        filter.use_group_names(&other.group_names());
+        assert!(filter.pass(other.get_mentity(other_entity)));
+        other.unset_group(other_entity, "visible");
+        assert!(!filter.pass(other.get_mentity(other_entity)));
+    }
+
+    #[test]
+    #[should_fail]
+    fn groups_without_names() {
+        let mut em = EntityMapper::new();
+        let entity = em.create_entity();
+
+        let mut filter = StandardEntityFilter::new();
+        filter.require_group("allies");
+        filter.pass(em.get_mentity(entity));
+    }
+
+    #[test]
+    fn relations() {
+        let mut em = EntityMapper::new();
+        let owner = em.create_entity();
+        let owned = em.create_entity();
+        em.relate::<Owns>(owner, owned);
+
+        let mut owners = StandardEntityFilter::new();
+        owners.require_relation::<Owns>();
+        let mut others = StandardEntityFilter::new();
+        others.forbid_relation::<Owns>();
+
+        assert!(owners.pass(em.get_mentity(owner)));
+        assert!(!owners.pass(em.get_mentity(owned)));
+        assert!(others.pass(em.get_mentity(owned)));
+
+        em.remove_entity(owned);
+        assert!(!owners.pass(em.get_mentity(owner)));
+        assert!(others.pass(em.get_mentity(owner)));
     }
 }
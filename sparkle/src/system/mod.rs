@@ -2,29 +2,61 @@
 
 use std::any::TypeId;
 
-use entity::{MetaEntity, EntityMapper, EntityObserver};
+use entity::{MetaEntity, EntityMapper, EntityObserver, GroupNames};
 use component::ComponentMapper;
-use command::{self, Command, CommandSender, CommandReceiver};
+use command::{Command, CommandSender, CommandReceiver, FromFn, Encoded, Channels, Scheduled};
+use record::{Recorder, Entry, Stream};
+use event::EventBus;
 
 pub use self::filter::{EntityView, StandardEntityView, EntityFilter, StandardEntityFilter};
+pub use self::deferred::EntityCommands;
 
 pub mod filter;
+pub mod deferred;
 
 /// The trait for systems.
 pub trait System: 'static {
+    /// Called when the system is inserted in a `SystemMapper`,
+    /// with the event bus shared by the systems.
+    ///
+    /// This is where a system creates its `EventReader`,
+    /// or keeps a clone of the bus to send events.
+    fn on_inserted(&mut self, _events: &EventBus) {}
+
+    /// Called with the group names of the entities,
+    /// when the system is inserted in a `SystemMapper` or when the names are changed.
+    ///
+    /// A system filtering entities by groups should give them to its views.
+    fn use_group_names(&mut self, _names: &GroupNames) {}
+
     /// Performs an update of the system according to the given delta time.
     ///
     /// This method is called every frame.
-    fn update(&mut self, _em: &mut EntityMapper, _component: &mut ComponentMapper, _dt: f32) {}
+    /// The changes recorded in `commands` are applied once it returns,
+    /// see the [deferred](deferred/index.html) module.
+    fn update(&mut self, _em: &mut EntityMapper, _component: &mut ComponentMapper,
+              _commands: &mut EntityCommands, _dt: f32) {}
 
     /// Performs an update of of the system.
     ///
     /// This method is called at a fixed timestep.
-    fn fixed_update(&mut self, _em: &mut EntityMapper, _component: &mut ComponentMapper) {}
+    /// The changes recorded in `commands` are applied once it returns.
+    fn fixed_update(&mut self, _em: &mut EntityMapper, _component: &mut ComponentMapper,
+                    _commands: &mut EntityCommands) {}
 
     /// Called when an entity has been changed.
     fn on_entity_changed(&mut self, _cm: &ComponentMapper, _mentity: &MetaEntity) {}
 
+    /// Called when an entity has been put to sleep.
+    ///
+    /// This is useful to pause the resources associated to the entity.
+    fn on_entity_slept(&mut self, _cm: &ComponentMapper, _mentity: &MetaEntity) {}
+
+    /// Called when an entity has been woken up.
+    ///
+    /// This is useful to resume the resources associated to the entity.
+    fn on_entity_woken(&mut self, _cm: &ComponentMapper, _mentity: &MetaEntity) {}
+
     /// Called when an entity has been removed.
     ///
     /// For convenience, entity metadatas clearing is delayed
@@ -37,33 +69,125 @@ pub type InterSystemCommand = Box<for<'a> Command<Args = (&'a mut EntityMapper,
 pub type Sender = CommandSender<InterSystemCommand>;
 pub type Receiver = CommandReceiver<InterSystemCommand>;
 
+/// A closure run as an `InterSystemCommand`.
+pub struct SystemFn<F>(pub F);
+
+impl<'a, F> Command for SystemFn<F>
+    where F: FnMut(&mut EntityMapper, &mut ComponentMapper) + 'static
+{
+    type Args = (&'a mut EntityMapper, &'a mut ComponentMapper);
+    fn run(&mut self, args: (&'a mut EntityMapper, &'a mut ComponentMapper)) {
+        let (em, cm) = args;
+        (self.0)(em, cm)
+    }
+}
+
+impl<F> FromFn<F> for InterSystemCommand
+    where F: FnMut(&mut EntityMapper, &mut ComponentMapper) + 'static
+{
+    fn from_fn(func: F) -> InterSystemCommand {
+        Box::new(SystemFn(func))
+    }
+}
+
+/// A command taken from a channel by `SystemMapper::process_commands`.
+enum Taken {
+    /// A pending command.
+    Pending(InterSystemCommand),
+    /// A due scheduled command, with the name of its channel.
+    Due(String, Scheduled<InterSystemCommand>)
+}
+
 /// Maps systems using `TypeId`s as identifiers.
 pub struct SystemMapper {
     slots: Vec<SystemSlot>,
-    commands: (Sender, Receiver)
+    commands: Channels<InterSystemCommand>,
+    events: EventBus,
+    names: GroupNames,
+    recorder: Option<Recorder>
 }
 
 impl SystemMapper {
-    /// Creates an empty `SystemMapper`.
+    /// Creates an empty `SystemMapper`, with its own event bus.
     pub fn new() -> SystemMapper {
+        SystemMapper::with_event_bus(EventBus::new())
+    }
+
+    /// Creates an empty `SystemMapper` giving the given event bus to its systems.
+    ///
+    /// This is done by `Space` with its own bus.
+    pub fn with_event_bus(events: EventBus) -> SystemMapper {
         SystemMapper {
             slots: Vec::new(),
-            commands: command::stream()
+            commands: Channels::new(),
+            events: events,
+            names: GroupNames::new(),
+            recorder: None
         }
     }
 
+    /// Returns the event bus given to the systems.
+    pub fn event_bus(&self) -> EventBus {
+        self.events.clone()
+    }
+
+    /// Gives the group names of an `EntityMapper` to the systems.
+    ///
+    /// This is done by `Space`, a standalone `SystemMapper` having its own names otherwise.
+    pub fn use_group_names(&mut self, names: GroupNames) {
+        for slot in self.slots.iter_mut() {
+            slot.system.use_group_names(&names);
+        }
+        self.names = names;
+    }
+
     /// Returns a CommandSender whose commands will be 
     /// executed between each system update.
+    ///
+    /// The commands are sent to the default channel.
     pub fn get_command_sender(&self) -> Sender {
-        self.commands.0.clone()
+        self.commands.default_sender()
+    }
+
+    /// Adds a command channel, processed after the existing ones, and returns its sender.
+    ///
+    /// If the channel already exists, its sender is returned.
+    pub fn add_command_channel(&mut self, name: &str) -> Sender {
+        self.commands.add(name)
+    }
+
+    /// Returns a sender to a command channel if it exists.
+    pub fn command_channel(&self, name: &str) -> Option<Sender> {
+        self.commands.sender(name)
+    }
+
+    /// Sets the processing order of the command channels.
+    ///
+    /// Panics if a given channel doesn't exist.
+    pub fn set_command_channel_order(&mut self, names: &[&str]) {
+        self.commands.set_order(names);
+    }
+
+    /// Records the inter-system commands with the given recorder.
+    ///
+    /// This is done by `Space::record`.
+    pub fn record(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    /// Stops recording the inter-system commands.
+    pub fn stop_recording(&mut self) {
+        self.recorder = None;
     }
 
     /// Inserts a system in the mapper.
     ///
-    /// The system will be awake by default.
-    pub fn insert<S>(&mut self, system: S)
+    /// The system will be awake by default, and is given the event bus and the group names.
+    pub fn insert<S>(&mut self, mut system: S)
         where S: System
     {
+        system.on_inserted(&self.events);
+        system.use_group_names(&self.names);
         self.slots.push(SystemSlot::new(system));
     }
 
@@ -113,22 +237,37 @@ impl SystemMapper {
     /// Updates systems with the given delta time.
     ///
     /// The systems are kept informed of entity changes between each system update.
+    /// The event buffers are then swapped, fixed updates leaving them untouched.
     pub fn update(&mut self, em: &mut EntityMapper, cm: &mut ComponentMapper, dt: f32) {
-        self.update_with(em, cm, |slot, em, cm| slot.system.update(em, cm, dt));
+        for receiver in self.commands.receivers_mut().into_iter() {
+            receiver.advance_time(dt);
+        }
+        self.update_with(em, cm, |slot, em, cm, commands| {
+            slot.system.update(em, cm, commands, dt)
+        });
+        self.events.flip();
     }
 
     /// Updates systems at a fixed timestep.
     ///
     /// The systems are kept informed of entity changes between each system update.
     pub fn fixed_update(&mut self, em: &mut EntityMapper, cm: &mut ComponentMapper) {
-        self.update_with(em, cm, |slot, em, cm| slot.system.fixed_update(em, cm));
+        for receiver in self.commands.receivers_mut().into_iter() {
+            receiver.advance_tick();
+        }
+        self.update_with(em, cm, |slot, em, cm, commands| {
+            slot.system.fixed_update(em, cm, commands)
+        });
     }
 
     /// Updates systems with the given function.
     ///
-    /// The systems are kept informed of entity changes between each system update.
+    /// Each system defers its changes in its own buffer, applied right after its update,
+    /// and the systems are kept informed of entity changes between each system update.
+    /// The tag conflicts of the deferred changes are sent on the event bus.
     fn update_with<F>(&mut self, em: &mut EntityMapper, cm: &mut ComponentMapper, mut func: F)
-        where F: FnMut(&mut SystemSlot, &mut EntityMapper, &mut ComponentMapper)
+        where F: FnMut(&mut SystemSlot, &mut EntityMapper, &mut ComponentMapper,
+                       &mut EntityCommands)
     {
         for i in range(0, self.slots.len()) {
             em.notify_events(cm, self);
@@ -136,16 +275,62 @@ impl SystemMapper {
 
             let slot = &mut self.slots[i];
             if slot.is_awake {
-                func(slot, em, cm);
+                let mut commands = EntityCommands::new(em);
+                func(slot, em, cm, &mut commands);
+                for conflict in commands.apply(em, cm).into_iter() {
+                    self.events.send(conflict);
+                }
             }
         }
     }
 
+    /// Runs the commands pending at the start of the call.
+    ///
+    /// The pending and due scheduled commands are taken from the channels before running any,
+    /// so that the commands they send are run on the next call,
+    /// and that a command sending itself again can't loop forever.
+    /// The channels are processed in order, the due scheduled commands of a channel
+    /// being run after its pending ones.
     fn process_commands(&mut self, em: &mut EntityMapper, cm: &mut ComponentMapper) {
-        while let Some(mut command) = self.commands.1.recv() {
-            command.run((em, cm));
+        let mut taken = Vec::new();
+        for (name, receiver) in self.commands.named_receivers_mut().into_iter() {
+            while let Some(command) = receiver.recv() {
+                taken.push(Taken::Pending(command));
+            }
+            while let Some(scheduled) = receiver.recv_due() {
+                taken.push(Taken::Due(name.to_string(), scheduled));
+            }
+        }
+
+        for entry in taken.into_iter() {
+            match entry {
+                Taken::Pending(mut command) => {
+                    self.record_command(|| command.encode());
+                    command.run((em, cm));
+                },
+                Taken::Due(channel, mut scheduled) => {
+                    // Cancelled by a command run before it.
+                    if scheduled.is_cancelled() {
+                        continue;
+                    }
+                    self.record_command(|| scheduled.command.encode());
+                    scheduled.command.run((em, cm));
+                    if let Some(receiver) = self.commands.receiver_mut(channel.as_slice()) {
+                        receiver.reschedule(scheduled);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Records an inter-system command if recording, encoding it only then.
+    fn record_command<F>(&self, encode: F)
+        where F: FnOnce() -> Option<Encoded>
+    {
+        if let Some(ref recorder) = self.recorder {
+            recorder.record(Entry::Command(Stream::System, encode()));
         }
-    } 
+    }
 }
 
 impl EntityObserver for SystemMapper {
@@ -156,6 +341,20 @@ impl EntityObserver for SystemMapper {
         }
     }
 
+    /// Notifies systems that an entity has been put to sleep.
+    fn notify_slept(&mut self, cm: &ComponentMapper, mentity: &MetaEntity) {
+        for slot in self.slots.iter_mut() {
+            slot.system.on_entity_slept(cm, mentity);
+        }
+    }
+
+    /// Notifies systems that an entity has been woken up.
+    fn notify_woken(&mut self, cm: &ComponentMapper, mentity: &MetaEntity) {
+        for slot in self.slots.iter_mut() {
+            slot.system.on_entity_woken(cm, mentity);
+        }
+    }
+
     /// Notifies systems that an entity has been removed.
     fn notify_removed(&mut self, cm: &ComponentMapper, mentity: &MetaEntity) {
         for slot in self.slots.iter_mut() {
@@ -185,3 +384,96 @@ impl SystemSlot {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use std::cell::Cell;
+
+    use entity::EntityMapper;
+    use component::ComponentMapper;
+    use command::Command;
+    use event::EventBus;
+    use super::{System, SystemMapper, Sender, EntityCommands};
+
+    /// A system doing nothing, the commands being processed before its updates.
+    struct Idle;
+
+    impl System for Idle {}
+
+    /// Creates an entity and sends itself again.
+    struct Echo {
+        runs: Rc<Cell<usize>>,
+        sender: Sender
+    }
+
+    impl<'a> Command for Echo {
+        type Args = (&'a mut EntityMapper, &'a mut ComponentMapper);
+        fn run(&mut self, args: (&'a mut EntityMapper, &'a mut ComponentMapper)) {
+            let (em, _) = args;
+            self.runs.set(self.runs.get() + 1);
+            em.create_entity();
+            let echo = Echo { runs: self.runs.clone(), sender: self.sender.clone() };
+            self.sender.send(Box::new(echo)).ok().unwrap();
+        }
+    }
+
+    #[test]
+    fn command_sending_itself() {
+        let mut em = EntityMapper::new();
+        let mut cm = ComponentMapper::new();
+        let mut sm = SystemMapper::new();
+        sm.insert(Idle);
+
+        let mut sender = sm.get_command_sender();
+        let runs = Rc::new(Cell::new(0));
+        let echo = Echo { runs: runs.clone(), sender: sender.clone() };
+        sender.send(Box::new(echo)).ok().unwrap();
+
+        sm.update(&mut em, &mut cm, 0.);
+        assert_eq!(runs.get(), 1);
+        sm.update(&mut em, &mut cm, 0.);
+        sm.fixed_update(&mut em, &mut cm);
+        assert_eq!(runs.get(), 3);
+        assert_eq!(em.entities().len(), 3);
+    }
+
+    #[derive(Clone, PartialEq, Show)]
+    struct Ping(usize);
+
+    /// Sends a ping on each update.
+    struct Pinger {
+        events: Option<EventBus>,
+        count: usize
+    }
+
+    impl System for Pinger {
+        fn on_inserted(&mut self, events: &EventBus) {
+            self.events = Some(events.clone());
+        }
+
+        fn update(&mut self, _em: &mut EntityMapper, _cm: &mut ComponentMapper,
+                  _commands: &mut EntityCommands, _dt: f32) {
+            self.count += 1;
+            self.events.as_ref().unwrap().send(Ping(self.count));
+        }
+    }
+
+    #[test]
+    fn events_flipped_without_space() {
+        let mut em = EntityMapper::new();
+        let mut cm = ComponentMapper::new();
+        let mut sm = SystemMapper::new();
+        sm.insert(Pinger { events: None, count: 0 });
+
+        let mut reader = sm.event_bus().reader();
+        assert!(reader.read::<Ping>().is_empty());
+        for _ in range(0, 10) {
+            sm.update(&mut em, &mut cm, 0.);
+        }
+        sm.fixed_update(&mut em, &mut cm);
+
+        // The unread events are dropped after two updates, but not after fixed updates.
+        assert_eq!(reader.read::<Ping>(), vec![Ping(10)]);
+    }
+}
@@ -0,0 +1,200 @@
+//! The fixed timestep related features.
+//!
+//! `Space::tick` runs as many fixed updates as the elapsed real time allows,
+//! then a regular update:
+//!
+//! ````ignore
+//! space.timestep.set_step(1. / 30.);
+//! loop {
+//!     space.tick(real_dt);
+//! }
+//! ````
+//!
+//! The number of fixed updates run by a single tick is capped,
+//! so that a slow frame doesn't lead to ever slower frames catching up.
+//! The time left over is kept for the next tick, and systems rendering
+//! between two fixed updates can interpolate with the `Interpolation` they were given:
+//!
+//! ````ignore
+//! let alpha = self.interpolation.alpha();
+//! let position = previous * (1. - alpha) + current * alpha;
+//! ````
+
+use std::rc::Rc;
+use std::cell::Cell;
+use std::num::Float;
+
+/// The default fixed timestep, in seconds.
+pub const DEFAULT_STEP: f32 = 1. / 60.;
+
+/// The default maximum number of fixed updates run by a single tick.
+pub const DEFAULT_MAX_STEPS: usize = 5;
+
+/// Accumulates real time to tell how many fixed updates to run.
+pub struct Timestep {
+    step: f32,
+    max_steps: usize,
+    accumulator: f32,
+    alpha: Rc<Cell<f32>>
+}
+
+impl Timestep {
+    /// Creates a `Timestep` with the given step, in seconds,
+    /// and maximum number of fixed updates per tick.
+    ///
+    /// Panics if the step isn't positive.
+    pub fn new(step: f32, max_steps: usize) -> Timestep {
+        let mut timestep = Timestep {
+            step: DEFAULT_STEP,
+            max_steps: max_steps,
+            accumulator: 0.,
+            alpha: Rc::new(Cell::new(0.))
+        };
+        timestep.set_step(step);
+        timestep
+    }
+
+    /// Returns the fixed timestep, in seconds.
+    pub fn step(&self) -> f32 {
+        self.step
+    }
+
+    /// Sets the fixed timestep, in seconds.
+    ///
+    /// Panics if the step isn't positive.
+    pub fn set_step(&mut self, step: f32) {
+        if !(step > 0.) {
+            panic!("The fixed timestep must be positive, got {}", step);
+        }
+        self.step = step;
+    }
+
+    /// Returns the maximum number of fixed updates run by a single tick.
+    pub fn max_steps(&self) -> usize {
+        self.max_steps
+    }
+
+    /// Sets the maximum number of fixed updates run by a single tick.
+    pub fn set_max_steps(&mut self, max_steps: usize) {
+        self.max_steps = max_steps;
+    }
+
+    /// Returns a handle to the interpolation alpha, updated on each tick.
+    pub fn interpolation(&self) -> Interpolation {
+        Interpolation {
+            alpha: self.alpha.clone()
+        }
+    }
+
+    /// Accumulates the given real delta time and returns the number of fixed updates to run.
+    ///
+    /// The whole steps over the maximum are dropped, keeping at most one step of time
+    /// for the next tick, and the interpolation alpha is set to the fraction of a step left over.
+    ///
+    /// Panics if the delta time is negative or not a number.
+    pub fn advance(&mut self, real_dt: f32) -> usize {
+        if !(real_dt >= 0.) {
+            panic!("The real delta time must be positive or zero, got {}", real_dt);
+        }
+        self.accumulator += real_dt;
+
+        let whole_steps = (self.accumulator / self.step).floor();
+        let is_capped = whole_steps > self.max_steps as f32;
+        let steps = if is_capped { self.max_steps } else { whole_steps as usize };
+
+        self.accumulator -= steps as f32 * self.step;
+        if is_capped && self.accumulator > self.step {
+            self.accumulator = self.step;
+        }
+
+        self.alpha.set(self.accumulator / self.step);
+        steps
+    }
+
+    /// Forgets the accumulated time.
+    pub fn reset(&mut self) {
+        self.accumulator = 0.;
+        self.alpha.set(0.);
+    }
+}
+
+/// A handle to the interpolation alpha of a `Timestep`.
+///
+/// The handle can be cloned, the clones sharing the same alpha.
+#[derive(Clone)]
+pub struct Interpolation {
+    alpha: Rc<Cell<f32>>
+}
+
+impl Interpolation {
+    /// Returns how far the last tick went between two fixed updates, in `[0, 1]`.
+    pub fn alpha(&self) -> f32 {
+        self.alpha.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Timestep;
+
+    #[test]
+    fn exact_multiples() {
+        let mut timestep = Timestep::new(0.25, 5);
+        let interpolation = timestep.interpolation();
+
+        assert_eq!(timestep.advance(0.25), 1);
+        assert_eq!(timestep.advance(0.75), 3);
+        assert_eq!(timestep.advance(0.), 0);
+        assert_eq!(interpolation.alpha(), 0.);
+    }
+
+    #[test]
+    fn leftover_alpha() {
+        let mut timestep = Timestep::new(0.25, 5);
+        let interpolation = timestep.interpolation();
+
+        assert_eq!(timestep.advance(0.375), 1);
+        assert_eq!(interpolation.alpha(), 0.5);
+        assert_eq!(timestep.advance(0.125), 1);
+        assert_eq!(interpolation.alpha(), 0.);
+    }
+
+    #[test]
+    fn cap() {
+        let mut timestep = Timestep::new(0.25, 2);
+        let interpolation = timestep.interpolation();
+
+        // The slow tick keeps a single step for the next one.
+        assert_eq!(timestep.advance(10.), 2);
+        assert_eq!(interpolation.alpha(), 1.);
+        assert_eq!(timestep.advance(0.125), 1);
+        assert_eq!(interpolation.alpha(), 0.5);
+
+        assert_eq!(timestep.advance(0.5), 2);
+        assert_eq!(interpolation.alpha(), 0.5);
+    }
+
+    #[test]
+    fn reset() {
+        let mut timestep = Timestep::new(0.25, 5);
+        let interpolation = timestep.interpolation();
+
+        timestep.advance(0.125);
+        timestep.reset();
+        assert_eq!(interpolation.alpha(), 0.);
+        assert_eq!(timestep.advance(0.125), 0);
+        assert_eq!(interpolation.alpha(), 0.5);
+    }
+
+    #[test]
+    #[should_fail]
+    fn negative_delta_time() {
+        Timestep::new(0.25, 5).advance(-1.);
+    }
+
+    #[test]
+    #[should_fail]
+    fn nan_delta_time() {
+        Timestep::new(0.25, 5).advance(0. / 0.);
+    }
+}